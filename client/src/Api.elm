@@ -1,312 +1,1213 @@
 module Api exposing (..)
 
-import Http exposing (Error(..))
-import Json.Decode as Decode exposing (Decoder, field)
+-- Generated from the Rust types by `cargo run -- elm-api`, don't edit by hand.
+
+import Json.Decode as Decode exposing (Decoder)
 import Json.Encode as Encode exposing (Value)
 
 
+protocolVersion : Int
+protocolVersion =
+    4
+
+
+andMap : Decoder a -> Decoder (a -> b) -> Decoder b
+andMap =
+    Decode.map2 (|>)
+
+
+encodeMaybe : (a -> Value) -> Maybe a -> Value
+encodeMaybe encode =
+    Maybe.withDefault Encode.null << Maybe.map encode
+
+
+type ChatBody
+    = Text String
+    | ChatBodyEmote Emote
+
+
+chatBodyDecoder : Decoder ChatBody
+chatBodyDecoder =
+    Decode.oneOf
+        [ Decode.field "Text" (Decode.map Text Decode.string)
+        , Decode.field "Emote" (Decode.map ChatBodyEmote emoteDecoder)
+        ]
+
+
+encodeChatBody : ChatBody -> Value
+encodeChatBody chatBody =
+    case chatBody of
+        Text value ->
+            Encode.object [ ( "Text", Encode.string value ) ]
+
+        ChatBodyEmote value ->
+            Encode.object [ ( "Emote", encodeEmote value ) ]
+
+
+type alias ChatEntry =
+    { userId : Int
+    , username : String
+    , body : ChatBody
+    , sentAtMs : Int
+    }
+
+
+chatEntryDecoder : Decoder ChatEntry
+chatEntryDecoder =
+    Decode.succeed ChatEntry
+        |> andMap (Decode.field "user_id" Decode.int)
+        |> andMap (Decode.field "username" Decode.string)
+        |> andMap (Decode.field "body" chatBodyDecoder)
+        |> andMap (Decode.field "sent_at_ms" Decode.int)
+
+
+encodeChatEntry : ChatEntry -> Value
+encodeChatEntry chatEntry =
+    Encode.object
+        [ ( "user_id", Encode.int chatEntry.userId )
+        , ( "username", Encode.string chatEntry.username )
+        , ( "body", encodeChatBody chatEntry.body )
+        , ( "sent_at_ms", Encode.int chatEntry.sentAtMs )
+        ]
+
+
+type ClientState
+    = Lobby LobbyDetails
+    | InGame InGameDetails
+    | Finished FinishedDetails
+    | Spectating SpectatingDetails
+
+
+type alias LobbyDetails =
+    { playerCount : Int
+    , playerReadyCount : Int
+    , players : List LobbyPlayer
+    , host : Bool
+    , inviteCode : Maybe String
+    , difficulty : Difficulty
+    , capacity : Int
+    , tickInterval : Int
+    , locked : Bool
+    }
+
+
+lobbyDetailsDecoder : Decoder LobbyDetails
+lobbyDetailsDecoder =
+    Decode.succeed LobbyDetails
+        |> andMap (Decode.field "player_count" Decode.int)
+        |> andMap (Decode.field "player_ready_count" Decode.int)
+        |> andMap (Decode.field "players" (Decode.list lobbyPlayerDecoder))
+        |> andMap (Decode.field "host" Decode.bool)
+        |> andMap (Decode.field "invite_code" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "difficulty" difficultyDecoder)
+        |> andMap (Decode.field "capacity" Decode.int)
+        |> andMap (Decode.field "tick_interval" Decode.int)
+        |> andMap (Decode.field "locked" Decode.bool)
+
+
+encodeLobbyDetails : LobbyDetails -> Value
+encodeLobbyDetails lobbyDetails =
+    Encode.object
+        [ ( "player_count", Encode.int lobbyDetails.playerCount )
+        , ( "player_ready_count", Encode.int lobbyDetails.playerReadyCount )
+        , ( "players", Encode.list encodeLobbyPlayer lobbyDetails.players )
+        , ( "host", Encode.bool lobbyDetails.host )
+        , ( "invite_code", encodeMaybe Encode.string lobbyDetails.inviteCode )
+        , ( "difficulty", encodeDifficulty lobbyDetails.difficulty )
+        , ( "capacity", Encode.int lobbyDetails.capacity )
+        , ( "tick_interval", Encode.int lobbyDetails.tickInterval )
+        , ( "locked", Encode.bool lobbyDetails.locked )
+        ]
+
+
+type alias InGameDetails =
+    { currentInstruction : String
+    , uiItems : List ClientUiItem
+    , instructionsExecuted : Int
+    , instructionsMissed : Int
+    , level : Int
+    , levelCount : Int
+    , levelProgress : Int
+    , levelTarget : Int
+    }
+
+
+inGameDetailsDecoder : Decoder InGameDetails
+inGameDetailsDecoder =
+    Decode.succeed InGameDetails
+        |> andMap (Decode.field "current_instruction" Decode.string)
+        |> andMap (Decode.field "ui_items" (Decode.list clientUiItemDecoder))
+        |> andMap (Decode.field "instructions_executed" Decode.int)
+        |> andMap (Decode.field "instructions_missed" Decode.int)
+        |> andMap (Decode.field "level" Decode.int)
+        |> andMap (Decode.field "level_count" Decode.int)
+        |> andMap (Decode.field "level_progress" Decode.int)
+        |> andMap (Decode.field "level_target" Decode.int)
+
+
+encodeInGameDetails : InGameDetails -> Value
+encodeInGameDetails inGameDetails =
+    Encode.object
+        [ ( "current_instruction", Encode.string inGameDetails.currentInstruction )
+        , ( "ui_items", Encode.list encodeClientUiItem inGameDetails.uiItems )
+        , ( "instructions_executed", Encode.int inGameDetails.instructionsExecuted )
+        , ( "instructions_missed", Encode.int inGameDetails.instructionsMissed )
+        , ( "level", Encode.int inGameDetails.level )
+        , ( "level_count", Encode.int inGameDetails.levelCount )
+        , ( "level_progress", Encode.int inGameDetails.levelProgress )
+        , ( "level_target", Encode.int inGameDetails.levelTarget )
+        ]
+
+
+type alias FinishedDetails =
+    { levelsCompleted : Int
+    , instructionsExecuted : Int
+    , instructionsMissed : Int
+    }
+
+
+finishedDetailsDecoder : Decoder FinishedDetails
+finishedDetailsDecoder =
+    Decode.succeed FinishedDetails
+        |> andMap (Decode.field "levels_completed" Decode.int)
+        |> andMap (Decode.field "instructions_executed" Decode.int)
+        |> andMap (Decode.field "instructions_missed" Decode.int)
+
+
+encodeFinishedDetails : FinishedDetails -> Value
+encodeFinishedDetails finishedDetails =
+    Encode.object
+        [ ( "levels_completed", Encode.int finishedDetails.levelsCompleted )
+        , ( "instructions_executed", Encode.int finishedDetails.instructionsExecuted )
+        , ( "instructions_missed", Encode.int finishedDetails.instructionsMissed )
+        ]
+
+
+type alias SpectatingDetails =
+    { players : List SpectatedPlayer
+    , instructionsExecuted : Int
+    , instructionsMissed : Int
+    , level : Int
+    , levelCount : Int
+    , levelProgress : Int
+    , levelTarget : Int
+    , finished : Bool
+    }
+
+
+spectatingDetailsDecoder : Decoder SpectatingDetails
+spectatingDetailsDecoder =
+    Decode.succeed SpectatingDetails
+        |> andMap (Decode.field "players" (Decode.list spectatedPlayerDecoder))
+        |> andMap (Decode.field "instructions_executed" Decode.int)
+        |> andMap (Decode.field "instructions_missed" Decode.int)
+        |> andMap (Decode.field "level" Decode.int)
+        |> andMap (Decode.field "level_count" Decode.int)
+        |> andMap (Decode.field "level_progress" Decode.int)
+        |> andMap (Decode.field "level_target" Decode.int)
+        |> andMap (Decode.field "finished" Decode.bool)
+
+
+encodeSpectatingDetails : SpectatingDetails -> Value
+encodeSpectatingDetails spectatingDetails =
+    Encode.object
+        [ ( "players", Encode.list encodeSpectatedPlayer spectatingDetails.players )
+        , ( "instructions_executed", Encode.int spectatingDetails.instructionsExecuted )
+        , ( "instructions_missed", Encode.int spectatingDetails.instructionsMissed )
+        , ( "level", Encode.int spectatingDetails.level )
+        , ( "level_count", Encode.int spectatingDetails.levelCount )
+        , ( "level_progress", Encode.int spectatingDetails.levelProgress )
+        , ( "level_target", Encode.int spectatingDetails.levelTarget )
+        , ( "finished", Encode.bool spectatingDetails.finished )
+        ]
+
+
+clientStateDecoder : Decoder ClientState
+clientStateDecoder =
+    Decode.oneOf
+        [ Decode.field "Lobby" (Decode.map Lobby lobbyDetailsDecoder)
+        , Decode.field "InGame" (Decode.map InGame inGameDetailsDecoder)
+        , Decode.field "Finished" (Decode.map Finished finishedDetailsDecoder)
+        , Decode.field "Spectating" (Decode.map Spectating spectatingDetailsDecoder)
+        ]
+
+
+encodeClientState : ClientState -> Value
+encodeClientState clientState =
+    case clientState of
+        Lobby details ->
+            Encode.object [ ( "Lobby", encodeLobbyDetails details ) ]
+
+        InGame details ->
+            Encode.object [ ( "InGame", encodeInGameDetails details ) ]
+
+        Finished details ->
+            Encode.object [ ( "Finished", encodeFinishedDetails details ) ]
+
+        Spectating details ->
+            Encode.object [ ( "Spectating", encodeSpectatingDetails details ) ]
+
+
+type alias ClientUiItem =
+    { id : Int
+    , label : String
+    , state : Int
+    , controlType : ControlType
+    , maxValue : Int
+    }
+
+
+clientUiItemDecoder : Decoder ClientUiItem
+clientUiItemDecoder =
+    Decode.succeed ClientUiItem
+        |> andMap (Decode.field "id" Decode.int)
+        |> andMap (Decode.field "label" Decode.string)
+        |> andMap (Decode.field "state" Decode.int)
+        |> andMap (Decode.field "control_type" controlTypeDecoder)
+        |> andMap (Decode.field "max_value" Decode.int)
+
+
+encodeClientUiItem : ClientUiItem -> Value
+encodeClientUiItem clientUiItem =
+    Encode.object
+        [ ( "id", Encode.int clientUiItem.id )
+        , ( "label", Encode.string clientUiItem.label )
+        , ( "state", Encode.int clientUiItem.state )
+        , ( "control_type", encodeControlType clientUiItem.controlType )
+        , ( "max_value", Encode.int clientUiItem.maxValue )
+        ]
+
+
+type ControlType
+    = Switch
+    | Dial
+    | Slider
+
+
+controlTypeDecoder : Decoder ControlType
+controlTypeDecoder =
+    Decode.string
+        |> Decode.andThen
+            (\tag ->
+                case tag of
+                    "Switch" ->
+                        Decode.succeed Switch
+
+                    "Dial" ->
+                        Decode.succeed Dial
+
+                    "Slider" ->
+                        Decode.succeed Slider
+
+                    _ ->
+                        Decode.fail ("Unknown ControlType: " ++ tag)
+            )
+
+
+encodeControlType : ControlType -> Value
+encodeControlType controlType =
+    case controlType of
+        Switch ->
+            Encode.string "Switch"
+
+        Dial ->
+            Encode.string "Dial"
+
+        Slider ->
+            Encode.string "Slider"
+
+
+controlTypeToString : ControlType -> String
+controlTypeToString controlType =
+    case controlType of
+        Switch ->
+            "Switch"
+
+        Dial ->
+            "Dial"
+
+        Slider ->
+            "Slider"
+
+
+type Difficulty
+    = Easy
+    | Normal
+    | Hard
+
+
+difficultyDecoder : Decoder Difficulty
+difficultyDecoder =
+    Decode.string
+        |> Decode.andThen
+            (\tag ->
+                case tag of
+                    "Easy" ->
+                        Decode.succeed Easy
+
+                    "Normal" ->
+                        Decode.succeed Normal
+
+                    "Hard" ->
+                        Decode.succeed Hard
+
+                    _ ->
+                        Decode.fail ("Unknown Difficulty: " ++ tag)
+            )
+
+
+encodeDifficulty : Difficulty -> Value
+encodeDifficulty difficulty =
+    case difficulty of
+        Easy ->
+            Encode.string "Easy"
+
+        Normal ->
+            Encode.string "Normal"
+
+        Hard ->
+            Encode.string "Hard"
+
+
+difficultyToString : Difficulty -> String
+difficultyToString difficulty =
+    case difficulty of
+        Easy ->
+            "Easy"
+
+        Normal ->
+            "Normal"
+
+        Hard ->
+            "Hard"
+
+
+type Emote
+    = Hurry
+    | Help
+    | NiceOne
+    | Oops
+    | Wait
+
+
+emoteDecoder : Decoder Emote
+emoteDecoder =
+    Decode.string
+        |> Decode.andThen
+            (\tag ->
+                case tag of
+                    "Hurry" ->
+                        Decode.succeed Hurry
+
+                    "Help" ->
+                        Decode.succeed Help
+
+                    "NiceOne" ->
+                        Decode.succeed NiceOne
+
+                    "Oops" ->
+                        Decode.succeed Oops
+
+                    "Wait" ->
+                        Decode.succeed Wait
+
+                    _ ->
+                        Decode.fail ("Unknown Emote: " ++ tag)
+            )
+
+
+encodeEmote : Emote -> Value
+encodeEmote emote =
+    case emote of
+        Hurry ->
+            Encode.string "Hurry"
+
+        Help ->
+            Encode.string "Help"
+
+        NiceOne ->
+            Encode.string "NiceOne"
+
+        Oops ->
+            Encode.string "Oops"
+
+        Wait ->
+            Encode.string "Wait"
+
+
+emoteToString : Emote -> String
+emoteToString emote =
+    case emote of
+        Hurry ->
+            "Hurry"
+
+        Help ->
+            "Help"
+
+        NiceOne ->
+            "NiceOne"
+
+        Oops ->
+            "Oops"
+
+        Wait ->
+            "Wait"
+
+
+type alias InGameDelta =
+    { currentInstruction : Maybe String
+    , itemStates : List ItemState
+    , instructionsExecuted : Maybe Int
+    , instructionsMissed : Maybe Int
+    , levelProgress : Maybe Int
+    , levelTarget : Maybe Int
+    }
+
+
+inGameDeltaDecoder : Decoder InGameDelta
+inGameDeltaDecoder =
+    Decode.succeed InGameDelta
+        |> andMap (Decode.field "current_instruction" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "item_states" (Decode.list itemStateDecoder))
+        |> andMap (Decode.field "instructions_executed" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "instructions_missed" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "level_progress" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "level_target" (Decode.nullable Decode.int))
+
+
+encodeInGameDelta : InGameDelta -> Value
+encodeInGameDelta inGameDelta =
+    Encode.object
+        [ ( "current_instruction", encodeMaybe Encode.string inGameDelta.currentInstruction )
+        , ( "item_states", Encode.list encodeItemState inGameDelta.itemStates )
+        , ( "instructions_executed", encodeMaybe Encode.int inGameDelta.instructionsExecuted )
+        , ( "instructions_missed", encodeMaybe Encode.int inGameDelta.instructionsMissed )
+        , ( "level_progress", encodeMaybe Encode.int inGameDelta.levelProgress )
+        , ( "level_target", encodeMaybe Encode.int inGameDelta.levelTarget )
+        ]
+
+
+type alias ItemState =
+    { id : Int
+    , state : Int
+    }
+
+
+itemStateDecoder : Decoder ItemState
+itemStateDecoder =
+    Decode.succeed ItemState
+        |> andMap (Decode.field "id" Decode.int)
+        |> andMap (Decode.field "state" Decode.int)
+
+
+encodeItemState : ItemState -> Value
+encodeItemState itemState =
+    Encode.object
+        [ ( "id", Encode.int itemState.id )
+        , ( "state", Encode.int itemState.state )
+        ]
+
+
+type alias LobbyPlayer =
+    { userId : Int
+    , username : String
+    , ready : Bool
+    }
+
+
+lobbyPlayerDecoder : Decoder LobbyPlayer
+lobbyPlayerDecoder =
+    Decode.succeed LobbyPlayer
+        |> andMap (Decode.field "user_id" Decode.int)
+        |> andMap (Decode.field "username" Decode.string)
+        |> andMap (Decode.field "ready" Decode.bool)
+
+
+encodeLobbyPlayer : LobbyPlayer -> Value
+encodeLobbyPlayer lobbyPlayer =
+    Encode.object
+        [ ( "user_id", Encode.int lobbyPlayer.userId )
+        , ( "username", Encode.string lobbyPlayer.username )
+        , ( "ready", Encode.bool lobbyPlayer.ready )
+        ]
+
+
 type alias Login =
     { username : String
     , password : String
+    , protocolVersion : Int
     }
 
 
+loginDecoder : Decoder Login
+loginDecoder =
+    Decode.succeed Login
+        |> andMap (Decode.field "username" Decode.string)
+        |> andMap (Decode.field "password" Decode.string)
+        |> andMap (Decode.field "protocol_version" Decode.int)
+
+
+encodeLogin : Login -> Value
+encodeLogin login =
+    Encode.object
+        [ ( "username", Encode.string login.username )
+        , ( "password", Encode.string login.password )
+        , ( "protocol_version", Encode.int login.protocolVersion )
+        ]
+
+
+type alias LoginFailureDetails =
+    { msg : String
+    }
+
+
+loginFailureDetailsDecoder : Decoder LoginFailureDetails
+loginFailureDetailsDecoder =
+    Decode.succeed LoginFailureDetails
+        |> andMap (Decode.field "msg" Decode.string)
+
+
+encodeLoginFailureDetails : LoginFailureDetails -> Value
+encodeLoginFailureDetails loginFailureDetails =
+    Encode.object
+        [ ( "msg", Encode.string loginFailureDetails.msg )
+        ]
+
+
 type LoginResponse
-    = LoginSuccess SuccessDetails
-    | LoginFailure FailureDetails
+    = Success LoginSuccessDetails
+    | Failure LoginFailureDetails
 
 
-type alias SuccessDetails =
-    { token : String }
+loginResponseDecoder : Decoder LoginResponse
+loginResponseDecoder =
+    Decode.oneOf
+        [ Decode.field "Success" (Decode.map Success loginSuccessDetailsDecoder)
+        , Decode.field "Failure" (Decode.map Failure loginFailureDetailsDecoder)
+        ]
+
 
+encodeLoginResponse : LoginResponse -> Value
+encodeLoginResponse loginResponse =
+    case loginResponse of
+        Success value ->
+            Encode.object [ ( "Success", encodeLoginSuccessDetails value ) ]
 
-type alias FailureDetails =
-    { msg : String }
+        Failure value ->
+            Encode.object [ ( "Failure", encodeLoginFailureDetails value ) ]
 
 
-loginEncoder : Login -> Value
-loginEncoder login =
+type alias LoginSuccessDetails =
+    { token : String
+    , username : String
+    , role : Role
+    }
+
+
+loginSuccessDetailsDecoder : Decoder LoginSuccessDetails
+loginSuccessDetailsDecoder =
+    Decode.succeed LoginSuccessDetails
+        |> andMap (Decode.field "token" Decode.string)
+        |> andMap (Decode.field "username" Decode.string)
+        |> andMap (Decode.field "role" roleDecoder)
+
+
+encodeLoginSuccessDetails : LoginSuccessDetails -> Value
+encodeLoginSuccessDetails loginSuccessDetails =
     Encode.object
-        [ ( "username", Encode.string login.username )
-        , ( "password", Encode.string login.password )
+        [ ( "token", Encode.string loginSuccessDetails.token )
+        , ( "username", Encode.string loginSuccessDetails.username )
+        , ( "role", encodeRole loginSuccessDetails.role )
         ]
 
 
-decodeLoginResponse : Decoder LoginResponse
-decodeLoginResponse =
-    Decode.oneOf [ decodeLoginSuccess, decodeLoginFailure ]
+type Role
+    = Player
+    | Moderator
+    | Admin
 
 
-decodeLoginSuccess : Decoder LoginResponse
-decodeLoginSuccess =
-    Decode.map LoginSuccess
-        (field "Success" decodeSuccessDetails)
+roleDecoder : Decoder Role
+roleDecoder =
+    Decode.string
+        |> Decode.andThen
+            (\tag ->
+                case tag of
+                    "Player" ->
+                        Decode.succeed Player
 
+                    "Moderator" ->
+                        Decode.succeed Moderator
 
-decodeSuccessDetails : Decoder SuccessDetails
-decodeSuccessDetails =
-    Decode.map SuccessDetails
-        (field "token" Decode.string)
+                    "Admin" ->
+                        Decode.succeed Admin
+
+                    _ ->
+                        Decode.fail ("Unknown Role: " ++ tag)
+            )
+
+
+encodeRole : Role -> Value
+encodeRole role =
+    case role of
+        Player ->
+            Encode.string "Player"
+
+        Moderator ->
+            Encode.string "Moderator"
+
+        Admin ->
+            Encode.string "Admin"
+
+
+roleToString : Role -> String
+roleToString role =
+    case role of
+        Player ->
+            "Player"
+
+        Moderator ->
+            "Moderator"
+
+        Admin ->
+            "Admin"
+
+
+type alias RoundSummary =
+    { id : String
+    , name : String
+    , hostName : String
+    , playerCount : Int
+    , capacity : Int
+    , readyCount : Int
+    , createdAtMs : Int
+    , passwordProtected : Bool
+    }
+
+
+roundSummaryDecoder : Decoder RoundSummary
+roundSummaryDecoder =
+    Decode.succeed RoundSummary
+        |> andMap (Decode.field "id" Decode.string)
+        |> andMap (Decode.field "name" Decode.string)
+        |> andMap (Decode.field "host_name" Decode.string)
+        |> andMap (Decode.field "player_count" Decode.int)
+        |> andMap (Decode.field "capacity" Decode.int)
+        |> andMap (Decode.field "ready_count" Decode.int)
+        |> andMap (Decode.field "created_at_ms" Decode.int)
+        |> andMap (Decode.field "password_protected" Decode.bool)
+
+
+encodeRoundSummary : RoundSummary -> Value
+encodeRoundSummary roundSummary =
+    Encode.object
+        [ ( "id", Encode.string roundSummary.id )
+        , ( "name", Encode.string roundSummary.name )
+        , ( "host_name", Encode.string roundSummary.hostName )
+        , ( "player_count", Encode.int roundSummary.playerCount )
+        , ( "capacity", Encode.int roundSummary.capacity )
+        , ( "ready_count", Encode.int roundSummary.readyCount )
+        , ( "created_at_ms", Encode.int roundSummary.createdAtMs )
+        , ( "password_protected", Encode.bool roundSummary.passwordProtected )
+        ]
+
+
+type alias SpectatedPlayer =
+    { userId : Int
+    , username : String
+    , currentInstruction : Maybe String
+    , uiItems : List ClientUiItem
+    }
 
 
-decodeLoginFailure : Decoder LoginResponse
-decodeLoginFailure =
-    Decode.map LoginFailure
-        (field "Failure" decodeFailureDetails)
+spectatedPlayerDecoder : Decoder SpectatedPlayer
+spectatedPlayerDecoder =
+    Decode.succeed SpectatedPlayer
+        |> andMap (Decode.field "user_id" Decode.int)
+        |> andMap (Decode.field "username" Decode.string)
+        |> andMap (Decode.field "current_instruction" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "ui_items" (Decode.list clientUiItemDecoder))
 
 
-decodeFailureDetails : Decoder FailureDetails
-decodeFailureDetails =
-    Decode.map FailureDetails
-        (field "msg" Decode.string)
+encodeSpectatedPlayer : SpectatedPlayer -> Value
+encodeSpectatedPlayer spectatedPlayer =
+    Encode.object
+        [ ( "user_id", Encode.int spectatedPlayer.userId )
+        , ( "username", Encode.string spectatedPlayer.username )
+        , ( "current_instruction", encodeMaybe Encode.string spectatedPlayer.currentInstruction )
+        , ( "ui_items", Encode.list encodeClientUiItem spectatedPlayer.uiItems )
+        ]
 
 
 type ToBackend
     = Init
-    | StartGame
+    | StartGame StartGameDetails
     | ToggleReady
-    | ChangeSetting ItemId Int
+    | ChangeSetting ChangeSettingDetails
     | GetAvailableRounds
-    | JoinGame RoundId
+    | JoinGame JoinGameDetails
+    | LeaveRound
+    | JoinByInviteCode String
+    | RegenerateInviteCode
+    | RevokeInviteCode
+    | Kick Int
+    | UpdateRoundSettings UpdateRoundSettingsDetails
+    | LockLobby Bool
+    | ForceStart
+    | QuickPlay (Maybe Int)
+    | LeaveQueue
+    | Spectate String
+    | Chat String
+    | ToBackendEmote Emote
+    | Resync
+
+
+type alias StartGameDetails =
+    { deck : Maybe String
+    , seed : Maybe Int
+    , name : Maybe String
+    , password : Maybe String
+    , private : Bool
+    }
 
 
-type alias ToBackendEnvelope =
-    { token : String
-    , toBackend : ToBackend
+startGameDetailsDecoder : Decoder StartGameDetails
+startGameDetailsDecoder =
+    Decode.succeed StartGameDetails
+        |> andMap (Decode.field "deck" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "seed" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "name" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "password" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "private" Decode.bool)
+
+
+encodeStartGameDetails : StartGameDetails -> Value
+encodeStartGameDetails startGameDetails =
+    Encode.object
+        [ ( "deck", encodeMaybe Encode.string startGameDetails.deck )
+        , ( "seed", encodeMaybe Encode.int startGameDetails.seed )
+        , ( "name", encodeMaybe Encode.string startGameDetails.name )
+        , ( "password", encodeMaybe Encode.string startGameDetails.password )
+        , ( "private", Encode.bool startGameDetails.private )
+        ]
+
+
+type alias ChangeSettingDetails =
+    { itemId : Int
+    , value : Int
     }
 
 
-toBackendEnvelopeEncoder : ToBackendEnvelope -> Value
-toBackendEnvelopeEncoder be =
+changeSettingDetailsDecoder : Decoder ChangeSettingDetails
+changeSettingDetailsDecoder =
+    Decode.succeed ChangeSettingDetails
+        |> andMap (Decode.field "item_id" Decode.int)
+        |> andMap (Decode.field "value" Decode.int)
+
+
+encodeChangeSettingDetails : ChangeSettingDetails -> Value
+encodeChangeSettingDetails changeSettingDetails =
     Encode.object
-        [ ( "token", Encode.string be.token )
-        , ( "to_backend", encodeToBackend be.toBackend )
+        [ ( "item_id", Encode.int changeSettingDetails.itemId )
+        , ( "value", Encode.int changeSettingDetails.value )
+        ]
+
+
+type alias JoinGameDetails =
+    { roundId : String
+    , password : Maybe String
+    }
+
+
+joinGameDetailsDecoder : Decoder JoinGameDetails
+joinGameDetailsDecoder =
+    Decode.succeed JoinGameDetails
+        |> andMap (Decode.field "round_id" Decode.string)
+        |> andMap (Decode.field "password" (Decode.nullable Decode.string))
+
+
+encodeJoinGameDetails : JoinGameDetails -> Value
+encodeJoinGameDetails joinGameDetails =
+    Encode.object
+        [ ( "round_id", Encode.string joinGameDetails.roundId )
+        , ( "password", encodeMaybe Encode.string joinGameDetails.password )
+        ]
+
+
+type alias UpdateRoundSettingsDetails =
+    { difficulty : Maybe Difficulty
+    , maxPlayers : Maybe Int
+    , tickInterval : Maybe Int
+    }
+
+
+updateRoundSettingsDetailsDecoder : Decoder UpdateRoundSettingsDetails
+updateRoundSettingsDetailsDecoder =
+    Decode.succeed UpdateRoundSettingsDetails
+        |> andMap (Decode.field "difficulty" (Decode.nullable difficultyDecoder))
+        |> andMap (Decode.field "max_players" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "tick_interval" (Decode.nullable Decode.int))
+
+
+encodeUpdateRoundSettingsDetails : UpdateRoundSettingsDetails -> Value
+encodeUpdateRoundSettingsDetails updateRoundSettingsDetails =
+    Encode.object
+        [ ( "difficulty", encodeMaybe encodeDifficulty updateRoundSettingsDetails.difficulty )
+        , ( "max_players", encodeMaybe Encode.int updateRoundSettingsDetails.maxPlayers )
+        , ( "tick_interval", encodeMaybe Encode.int updateRoundSettingsDetails.tickInterval )
+        ]
+
+
+toBackendDecoder : Decoder ToBackend
+toBackendDecoder =
+    Decode.oneOf
+        [ Decode.string
+            |> Decode.andThen
+                (\tag ->
+                    case tag of
+                        "Init" ->
+                            Decode.succeed Init
+
+                        "ToggleReady" ->
+                            Decode.succeed ToggleReady
+
+                        "GetAvailableRounds" ->
+                            Decode.succeed GetAvailableRounds
+
+                        "LeaveRound" ->
+                            Decode.succeed LeaveRound
+
+                        "RegenerateInviteCode" ->
+                            Decode.succeed RegenerateInviteCode
+
+                        "RevokeInviteCode" ->
+                            Decode.succeed RevokeInviteCode
+
+                        "ForceStart" ->
+                            Decode.succeed ForceStart
+
+                        "LeaveQueue" ->
+                            Decode.succeed LeaveQueue
+
+                        "Resync" ->
+                            Decode.succeed Resync
+
+                        _ ->
+                            Decode.fail ("Unknown ToBackend: " ++ tag)
+                )
+        , Decode.field "StartGame" (Decode.map StartGame startGameDetailsDecoder)
+        , Decode.field "ChangeSetting" (Decode.map ChangeSetting changeSettingDetailsDecoder)
+        , Decode.field "JoinGame" (Decode.map JoinGame joinGameDetailsDecoder)
+        , Decode.field "JoinByInviteCode" (Decode.map JoinByInviteCode (Decode.field "invite_code" Decode.string))
+        , Decode.field "Kick" (Decode.map Kick (Decode.field "user_id" Decode.int))
+        , Decode.field "UpdateRoundSettings" (Decode.map UpdateRoundSettings updateRoundSettingsDetailsDecoder)
+        , Decode.field "LockLobby" (Decode.map LockLobby (Decode.field "locked" Decode.bool))
+        , Decode.field "QuickPlay" (Decode.map QuickPlay (Decode.field "preferred_size" (Decode.nullable Decode.int)))
+        , Decode.field "Spectate" (Decode.map Spectate (Decode.field "round_id" Decode.string))
+        , Decode.field "Chat" (Decode.map Chat (Decode.field "text" Decode.string))
+        , Decode.field "Emote" (Decode.map ToBackendEmote (Decode.field "emote" emoteDecoder))
         ]
 
 
 encodeToBackend : ToBackend -> Value
-encodeToBackend tb =
-    case tb of
+encodeToBackend toBackend =
+    case toBackend of
         Init ->
             Encode.string "Init"
 
-        StartGame ->
-            Encode.string "StartGame"
+        StartGame details ->
+            Encode.object [ ( "StartGame", encodeStartGameDetails details ) ]
 
         ToggleReady ->
             Encode.string "ToggleReady"
 
-        ChangeSetting item_id value ->
-            Encode.object
-                [ ( "ChangeSetting"
-                  , Encode.object
-                        [ ( "item_id", Encode.int item_id )
-                        , ( "value", Encode.int value )
-                        ]
-                  )
-                ]
+        ChangeSetting details ->
+            Encode.object [ ( "ChangeSetting", encodeChangeSettingDetails details ) ]
 
         GetAvailableRounds ->
             Encode.string "GetAvailableRounds"
 
-        JoinGame roundId ->
-            Encode.object
-                [ ( "JoinGame", Encode.object [ ( "round_id", Encode.string roundId ) ] )
-                ]
+        JoinGame details ->
+            Encode.object [ ( "JoinGame", encodeJoinGameDetails details ) ]
 
+        LeaveRound ->
+            Encode.string "LeaveRound"
 
-type ClientState
-    = Lobby LobbyDetails
-    | InLevel InLevelDetails
-
-
-type alias LobbyDetails =
-    { playerCount : Int, playerReadyCount : Int }
+        JoinByInviteCode value ->
+            Encode.object [ ( "JoinByInviteCode", Encode.object [ ( "invite_code", Encode.string value ) ] ) ]
 
+        RegenerateInviteCode ->
+            Encode.string "RegenerateInviteCode"
 
-type alias InLevelDetails =
-    { currentInstruction : String
-    , uiItems : List UiItem
-    , instructionsExecuted : Int
-    , instructionsMissed : Int
-    }
+        RevokeInviteCode ->
+            Encode.string "RevokeInviteCode"
 
+        Kick value ->
+            Encode.object [ ( "Kick", Encode.object [ ( "user_id", Encode.int value ) ] ) ]
 
-type alias ItemId =
-    Int
+        UpdateRoundSettings details ->
+            Encode.object [ ( "UpdateRoundSettings", encodeUpdateRoundSettingsDetails details ) ]
 
+        LockLobby value ->
+            Encode.object [ ( "LockLobby", Encode.object [ ( "locked", Encode.bool value ) ] ) ]
 
-type alias UiItem =
-    { id : ItemId
-    , label : String
-    , state : Int
-    , maxValue : Int
-    }
+        ForceStart ->
+            Encode.string "ForceStart"
 
+        QuickPlay value ->
+            Encode.object [ ( "QuickPlay", Encode.object [ ( "preferred_size", encodeMaybe Encode.int value ) ] ) ]
 
-decodeClientState : Decoder ClientState
-decodeClientState =
-    Decode.oneOf [ decodeInLobby, decodeInLevel ]
+        LeaveQueue ->
+            Encode.string "LeaveQueue"
 
+        Spectate value ->
+            Encode.object [ ( "Spectate", Encode.object [ ( "round_id", Encode.string value ) ] ) ]
 
-decodeInLobby : Decoder ClientState
-decodeInLobby =
-    let
-        details =
-            Decode.map2 LobbyDetails
-                (field "player_count" Decode.int)
-                (field "player_ready_count" Decode.int)
-    in
-    Decode.map Lobby
-        (field "Lobby" details)
+        Chat value ->
+            Encode.object [ ( "Chat", Encode.object [ ( "text", Encode.string value ) ] ) ]
 
+        ToBackendEmote value ->
+            Encode.object [ ( "Emote", Encode.object [ ( "emote", encodeEmote value ) ] ) ]
 
-decodeInLevel : Decoder ClientState
-decodeInLevel =
-    let
-        details =
-            Decode.map4 InLevelDetails
-                (field "current_instruction" Decode.string)
-                (field "ui_items" (Decode.list decodeUiItem))
-                (field "instructions_executed" Decode.int)
-                (field "instructions_missed" Decode.int)
-    in
-    Decode.map InLevel
-        (field "InGame" details)
+        Resync ->
+            Encode.string "Resync"
 
 
-decodeUiItem : Decoder UiItem
-decodeUiItem =
-    Decode.map4 UiItem
-        (field "id" Decode.int)
-        (field "label" Decode.string)
-        (field "state" Decode.int)
-        (field "max_value" Decode.int)
+type alias ToBackendEnvelope =
+    { token : String
+    , toBackend : ToBackend
+    , requestId : Maybe String
+    , asModerator : Bool
+    }
 
 
-type ToClientEnvelope
-    = SuperSeeded
-    | AppMsg ToClient
+toBackendEnvelopeDecoder : Decoder ToBackendEnvelope
+toBackendEnvelopeDecoder =
+    Decode.succeed ToBackendEnvelope
+        |> andMap (Decode.field "token" Decode.string)
+        |> andMap (Decode.field "to_backend" toBackendDecoder)
+        |> andMap (Decode.field "request_id" (Decode.nullable Decode.string))
+        |> andMap (Decode.field "as_moderator" Decode.bool)
 
 
-type alias RoundId =
-    String
+encodeToBackendEnvelope : ToBackendEnvelope -> Value
+encodeToBackendEnvelope toBackendEnvelope =
+    Encode.object
+        [ ( "token", Encode.string toBackendEnvelope.token )
+        , ( "to_backend", encodeToBackend toBackendEnvelope.toBackend )
+        , ( "request_id", encodeMaybe Encode.string toBackendEnvelope.requestId )
+        , ( "as_moderator", Encode.bool toBackendEnvelope.asModerator )
+        ]
 
 
 type ToClient
     = HelloClient
-    | UpdateGameState UpdateGameStateDetails
+    | UpdateGameState ClientState
     | AvailableRounds AvailableRoundsDetails
-    | EnterRound UpdateGameStateDetails
+    | EnterRound ClientState
+    | QueuePosition QueuePositionDetails
+    | MatchFound String
+    | ChatMessage ChatEntry
+    | ServerNotice String
+    | JoinFailed String
+    | GameSnapshot GameSnapshotDetails
+    | GameDelta GameDeltaDetails
 
 
 type alias AvailableRoundsDetails =
-    { roundIds : List RoundId }
+    { rounds : List RoundSummary
+    , runningRounds : List RoundSummary
+    , decks : List String
+    }
 
 
-type alias UpdateGameStateDetails =
-    { clientState : ClientState }
+availableRoundsDetailsDecoder : Decoder AvailableRoundsDetails
+availableRoundsDetailsDecoder =
+    Decode.succeed AvailableRoundsDetails
+        |> andMap (Decode.field "rounds" (Decode.list roundSummaryDecoder))
+        |> andMap (Decode.field "running_rounds" (Decode.list roundSummaryDecoder))
+        |> andMap (Decode.field "decks" (Decode.list Decode.string))
 
 
-eventDecoder : Decoder ToClientEnvelope
-eventDecoder =
-    Decode.oneOf [ superSeededDecoder, appMsgDecoder ]
+encodeAvailableRoundsDetails : AvailableRoundsDetails -> Value
+encodeAvailableRoundsDetails availableRoundsDetails =
+    Encode.object
+        [ ( "rounds", Encode.list encodeRoundSummary availableRoundsDetails.rounds )
+        , ( "running_rounds", Encode.list encodeRoundSummary availableRoundsDetails.runningRounds )
+        , ( "decks", Encode.list Encode.string availableRoundsDetails.decks )
+        ]
 
 
-superSeededDecoder : Decoder ToClientEnvelope
-superSeededDecoder =
-    Decode.field "SuperSeeded" (Decode.list Decode.string)
-        |> Decode.andThen
-            (\_ -> Decode.succeed SuperSeeded)
+type alias QueuePositionDetails =
+    { position : Maybe Int
+    , queueLength : Int
+    }
+
+
+queuePositionDetailsDecoder : Decoder QueuePositionDetails
+queuePositionDetailsDecoder =
+    Decode.succeed QueuePositionDetails
+        |> andMap (Decode.field "position" (Decode.nullable Decode.int))
+        |> andMap (Decode.field "queue_length" Decode.int)
+
+
+encodeQueuePositionDetails : QueuePositionDetails -> Value
+encodeQueuePositionDetails queuePositionDetails =
+    Encode.object
+        [ ( "position", encodeMaybe Encode.int queuePositionDetails.position )
+        , ( "queue_length", Encode.int queuePositionDetails.queueLength )
+        ]
+
+
+type alias GameSnapshotDetails =
+    { seq : Int
+    , clientState : ClientState
+    }
+
+
+gameSnapshotDetailsDecoder : Decoder GameSnapshotDetails
+gameSnapshotDetailsDecoder =
+    Decode.succeed GameSnapshotDetails
+        |> andMap (Decode.field "seq" Decode.int)
+        |> andMap (Decode.field "client_state" clientStateDecoder)
+
+
+encodeGameSnapshotDetails : GameSnapshotDetails -> Value
+encodeGameSnapshotDetails gameSnapshotDetails =
+    Encode.object
+        [ ( "seq", Encode.int gameSnapshotDetails.seq )
+        , ( "client_state", encodeClientState gameSnapshotDetails.clientState )
+        ]
+
+
+type alias GameDeltaDetails =
+    { seq : Int
+    , delta : InGameDelta
+    }
+
+
+gameDeltaDetailsDecoder : Decoder GameDeltaDetails
+gameDeltaDetailsDecoder =
+    Decode.succeed GameDeltaDetails
+        |> andMap (Decode.field "seq" Decode.int)
+        |> andMap (Decode.field "delta" inGameDeltaDecoder)
 
 
-appMsgDecoder : Decoder ToClientEnvelope
-appMsgDecoder =
-    Decode.map AppMsg
-        (Decode.field "AppMsg" toClientDecoder)
+encodeGameDeltaDetails : GameDeltaDetails -> Value
+encodeGameDeltaDetails gameDeltaDetails =
+    Encode.object
+        [ ( "seq", Encode.int gameDeltaDetails.seq )
+        , ( "delta", encodeInGameDelta gameDeltaDetails.delta )
+        ]
 
 
 toClientDecoder : Decoder ToClient
 toClientDecoder =
-    Decode.oneOf [ decodeHelloClient, decodeUpdateGameState, decodeAvailableRounds, decodeEnterRound ]
+    Decode.oneOf
+        [ Decode.string
+            |> Decode.andThen
+                (\tag ->
+                    case tag of
+                        "HelloClient" ->
+                            Decode.succeed HelloClient
+
+                        _ ->
+                            Decode.fail ("Unknown ToClient: " ++ tag)
+                )
+        , Decode.field "UpdateGameState" (Decode.map UpdateGameState (Decode.field "client_state" clientStateDecoder))
+        , Decode.field "AvailableRounds" (Decode.map AvailableRounds availableRoundsDetailsDecoder)
+        , Decode.field "EnterRound" (Decode.map EnterRound (Decode.field "client_state" clientStateDecoder))
+        , Decode.field "QueuePosition" (Decode.map QueuePosition queuePositionDetailsDecoder)
+        , Decode.field "MatchFound" (Decode.map MatchFound (Decode.field "round_id" Decode.string))
+        , Decode.field "ChatMessage" (Decode.map ChatMessage (Decode.field "message" chatEntryDecoder))
+        , Decode.field "ServerNotice" (Decode.map ServerNotice (Decode.field "message" Decode.string))
+        , Decode.field "JoinFailed" (Decode.map JoinFailed (Decode.field "message" Decode.string))
+        , Decode.field "GameSnapshot" (Decode.map GameSnapshot gameSnapshotDetailsDecoder)
+        , Decode.field "GameDelta" (Decode.map GameDelta gameDeltaDetailsDecoder)
+        ]
 
 
-decodeHelloClient : Decoder ToClient
-decodeHelloClient =
-    Decode.string
-        |> Decode.andThen
-            (\s ->
-                case s of
-                    "HelloClient" ->
-                        Decode.succeed HelloClient
+encodeToClient : ToClient -> Value
+encodeToClient toClient =
+    case toClient of
+        HelloClient ->
+            Encode.string "HelloClient"
 
-                    _ ->
-                        Decode.fail <| "Unkown ToClient: " ++ s
-            )
+        UpdateGameState value ->
+            Encode.object [ ( "UpdateGameState", Encode.object [ ( "client_state", encodeClientState value ) ] ) ]
+
+        AvailableRounds details ->
+            Encode.object [ ( "AvailableRounds", encodeAvailableRoundsDetails details ) ]
 
+        EnterRound value ->
+            Encode.object [ ( "EnterRound", Encode.object [ ( "client_state", encodeClientState value ) ] ) ]
 
-decodeUpdateGameState : Decoder ToClient
-decodeUpdateGameState =
-    let
-        updateGameStateDetailsDecoder =
-            Decode.map UpdateGameStateDetails
-                (field "client_state" decodeClientState)
-    in
-    Decode.map UpdateGameState
-        (field "UpdateGameState" updateGameStateDetailsDecoder)
+        QueuePosition details ->
+            Encode.object [ ( "QueuePosition", encodeQueuePositionDetails details ) ]
 
+        MatchFound value ->
+            Encode.object [ ( "MatchFound", Encode.object [ ( "round_id", Encode.string value ) ] ) ]
 
-decodeEnterRound : Decoder ToClient
-decodeEnterRound =
-    let
-        updateGameStateDetailsDecoder =
-            Decode.map UpdateGameStateDetails
-                (field "client_state" decodeClientState)
-    in
-    Decode.map EnterRound
-        (field "EnterRound" updateGameStateDetailsDecoder)
+        ChatMessage value ->
+            Encode.object [ ( "ChatMessage", Encode.object [ ( "message", encodeChatEntry value ) ] ) ]
 
+        ServerNotice value ->
+            Encode.object [ ( "ServerNotice", Encode.object [ ( "message", Encode.string value ) ] ) ]
 
-decodeAvailableRounds : Decoder ToClient
-decodeAvailableRounds =
-    let
-        availableRoundsDetailsDecoder =
-            Decode.map AvailableRoundsDetails
-                (field "round_ids" <| Decode.list Decode.string)
-    in
-    Decode.map AvailableRounds
-        (field "AvailableRounds" availableRoundsDetailsDecoder)
+        JoinFailed value ->
+            Encode.object [ ( "JoinFailed", Encode.object [ ( "message", Encode.string value ) ] ) ]
 
+        GameSnapshot details ->
+            Encode.object [ ( "GameSnapshot", encodeGameSnapshotDetails details ) ]
 
-sendAction : (Result Http.Error () -> msg) -> String -> ToBackend -> Cmd msg
-sendAction actionConfirmationHandler token toBackend =
-    Http.post
-        { url = "/action"
-        , body = Http.jsonBody <| toBackendEnvelopeEncoder { token = token, toBackend = toBackend }
-        , expect = Http.expectWhatever actionConfirmationHandler
-        }
+        GameDelta details ->
+            Encode.object [ ( "GameDelta", encodeGameDeltaDetails details ) ]
 
 
-httpErrorToString : Http.Error -> String
-httpErrorToString error =
-    case error of
-        BadUrl url ->
-            "The URL " ++ url ++ " was invalid"
+type ToClientEnvelope
+    = SuperSeeded
+    | AppMsg ToClient
+    | UpgradeRequired UpgradeRequiredDetails
 
-        Timeout ->
-            "Unable to reach the server, try again"
 
-        NetworkError ->
-            "Unable to reach the server, check your network connection"
+type alias UpgradeRequiredDetails =
+    { serverVersion : Int
+    , minVersion : Int
+    }
+
+
+upgradeRequiredDetailsDecoder : Decoder UpgradeRequiredDetails
+upgradeRequiredDetailsDecoder =
+    Decode.succeed UpgradeRequiredDetails
+        |> andMap (Decode.field "server_version" Decode.int)
+        |> andMap (Decode.field "min_version" Decode.int)
+
+
+encodeUpgradeRequiredDetails : UpgradeRequiredDetails -> Value
+encodeUpgradeRequiredDetails upgradeRequiredDetails =
+    Encode.object
+        [ ( "server_version", Encode.int upgradeRequiredDetails.serverVersion )
+        , ( "min_version", Encode.int upgradeRequiredDetails.minVersion )
+        ]
+
+
+toClientEnvelopeDecoder : Decoder ToClientEnvelope
+toClientEnvelopeDecoder =
+    Decode.oneOf
+        [ Decode.field "SuperSeeded" (Decode.succeed SuperSeeded)
+        , Decode.field "AppMsg" (Decode.map AppMsg toClientDecoder)
+        , Decode.field "UpgradeRequired" (Decode.map UpgradeRequired upgradeRequiredDetailsDecoder)
+        ]
 
-        BadStatus 500 ->
-            "The server had a problem, try again later"
 
-        BadStatus 400 ->
-            "Verify your information and try again"
+encodeToClientEnvelope : ToClientEnvelope -> Value
+encodeToClientEnvelope toClientEnvelope =
+    case toClientEnvelope of
+        SuperSeeded ->
+            Encode.object [ ( "SuperSeeded", Encode.list identity [] ) ]
 
-        BadStatus _ ->
-            "Unknown error"
+        AppMsg value ->
+            Encode.object [ ( "AppMsg", encodeToClient value ) ]
 
-        BadBody errorMessage ->
-            errorMessage
+        UpgradeRequired details ->
+            Encode.object [ ( "UpgradeRequired", encodeUpgradeRequiredDetails details ) ]
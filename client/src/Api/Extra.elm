@@ -0,0 +1,50 @@
+module Api.Extra exposing (chatEntryToString, httpErrorToString, sendAction)
+
+{-| Hand written helpers around the generated Api module. -}
+
+import Api exposing (ChatBody(..), ChatEntry, ToBackend)
+import Http exposing (Error(..))
+
+
+chatEntryToString : ChatEntry -> String
+chatEntryToString { username, body } =
+    case body of
+        Text chatText ->
+            username ++ ": " ++ chatText
+
+        ChatBodyEmote emote ->
+            username ++ " " ++ Api.emoteToString emote
+
+
+sendAction : (Result Http.Error () -> msg) -> String -> ToBackend -> Cmd msg
+sendAction actionConfirmationHandler token toBackend =
+    Http.post
+        { url = "/action"
+        , body = Http.jsonBody <| Api.encodeToBackendEnvelope { token = token, toBackend = toBackend, requestId = Nothing, asModerator = False }
+        , expect = Http.expectWhatever actionConfirmationHandler
+        }
+
+
+httpErrorToString : Http.Error -> String
+httpErrorToString error =
+    case error of
+        BadUrl url ->
+            "The URL " ++ url ++ " was invalid"
+
+        Timeout ->
+            "Unable to reach the server, try again"
+
+        NetworkError ->
+            "Unable to reach the server, check your network connection"
+
+        BadStatus 500 ->
+            "The server had a problem, try again later"
+
+        BadStatus 400 ->
+            "Verify your information and try again"
+
+        BadStatus _ ->
+            "Unknown error"
+
+        BadBody errorMessage ->
+            errorMessage
@@ -0,0 +1,36 @@
+module Chat exposing (append, appendNotice, view)
+
+import Api exposing (ChatBody(..), ChatEntry, Emote(..), ToBackend)
+import Api.Extra
+import Html.Styled exposing (Html, button, div, input, li, text, ul)
+import Html.Styled.Attributes exposing (placeholder, value)
+import Html.Styled.Events exposing (onClick, onInput)
+
+
+historyLength : Int
+historyLength =
+    20
+
+
+append : ChatEntry -> List ChatEntry -> List ChatEntry
+append entry chat =
+    List.drop (List.length chat + 1 - historyLength) chat ++ [ entry ]
+
+
+appendNotice : String -> List ChatEntry -> List ChatEntry
+appendNotice message =
+    append { userId = 0, username = "server", body = Text message, sentAtMs = 0 }
+
+
+view : { chat : List ChatEntry, chatInput : String } -> (String -> msg) -> msg -> (ToBackend -> msg) -> Html msg
+view { chat, chatInput } onChatInput sendChat send =
+    let
+        mkEmote emote =
+            button [ onClick <| send <| Api.ToBackendEmote emote ] [ text <| Api.emoteToString emote ]
+    in
+    div []
+        [ ul [] <| List.map (\entry -> li [] [ text <| Api.Extra.chatEntryToString entry ]) chat
+        , input [ placeholder "say something", value chatInput, onInput onChatInput ] []
+        , button [ onClick sendChat ] [ text "send" ]
+        , div [] <| List.map mkEmote [ Hurry, Help, NiceOne, Oops, Wait ]
+        ]
@@ -1,6 +1,7 @@
 port module Main exposing (..)
 
-import Api exposing (ClientState(..), ToBackend(..), ToClient(..), sendAction)
+import Api exposing (ClientState(..), ToBackend(..), ToClient(..))
+import Api.Extra exposing (sendAction)
 import Browser
 import Css exposing (Rem, Style, absolute, backgroundColor, hex, position, px, rem, top, transform, translateX, translateY, vh, vw, width)
 import Css.Transitions exposing (easeIn, easeInOut, transition)
@@ -77,7 +78,10 @@ type Msg
     | SSEConnected
     | CouldNotSendAction
     | CouldNotDecodeEvent
-    | ChangeToRound Session ClientState
+    | OutOfDate
+    | UpgradeAvailable
+    | ChangeToRound Session (Maybe Int) ClientState
+    | ChangeToMenu Session ToClient
 
 
 update : Msg -> Model -> ( Model, Cmd Msg )
@@ -93,11 +97,26 @@ update msg model =
                     Cmd.none
             )
 
-        ( ChangeToRound session clientState, OnRound roundModel ) ->
-            ( OnRound (Round.updateClientState session clientState (Just roundModel)), Cmd.none )
+        ( ChangeToRound session seq clientState, OnRound roundModel ) ->
+            let
+                roundModel_ =
+                    Round.updateClientState session clientState (Just roundModel)
+            in
+            ( OnRound { roundModel_ | seq = seq }, Cmd.none )
 
-        ( ChangeToRound session clientState, _ ) ->
-            ( OnRound (Round.updateClientState session clientState Nothing), Cmd.none )
+        ( ChangeToRound session seq clientState, _ ) ->
+            let
+                roundModel =
+                    Round.updateClientState session clientState Nothing
+            in
+            ( OnRound { roundModel | seq = seq }, Cmd.none )
+
+        ( ChangeToMenu session toClient, _ ) ->
+            let
+                ( menuModel, cmd ) =
+                    Menu.update (Menu.gotEvent toClient) (Menu.init session)
+            in
+            ( OnMenu menuModel, Cmd.map ForMenu cmd )
 
         ( ForLogin ((Login.GotLoginResponse httpResponse) as subMsg), OnLogin subModel ) ->
             let
@@ -105,7 +124,7 @@ update msg model =
                     case httpResponse of
                         Ok loginResponse ->
                             case loginResponse of
-                                Api.LoginSuccess sessionData ->
+                                Api.Success sessionData ->
                                     Just
                                         ( OnMenu <| Menu.init sessionData
                                         , connectToSSE sessionData.token
@@ -144,6 +163,11 @@ update msg model =
             , connectToSSE ""
             )
 
+        ( OutOfDate, _ ) ->
+            ( OnLogin (Login.init <| Just "This version of the game is out of date, please reload the page")
+            , connectToSSE ""
+            )
+
         ( ForRound subMsg, OnRound subModel ) ->
             let
                 ( updateSubModel, cmd ) =
@@ -174,7 +198,7 @@ onEvent : Model -> Value -> Msg
 onEvent model value =
     let
         decoderResult =
-            Decode.decodeValue Api.eventDecoder value
+            Decode.decodeValue Api.toClientEnvelopeDecoder value
 
         maybeSession =
             sessionFromModel model
@@ -182,11 +206,19 @@ onEvent model value =
     case ( decoderResult, maybeSession ) of
         ( Ok (Api.AppMsg toClient), Just session ) ->
             case ( toClient, model ) of
-                ( UpdateGameState { clientState }, _ ) ->
-                    ChangeToRound session clientState
+                ( UpdateGameState clientState, _ ) ->
+                    ChangeToRound session Nothing clientState
+
+                ( EnterRound clientState, _ ) ->
+                    ChangeToRound session Nothing clientState
 
-                ( EnterRound { clientState }, _ ) ->
-                    ChangeToRound session clientState
+                -- e.g. after a reload during a level, the deltas build on its seq
+                ( GameSnapshot { seq, clientState }, OnMenu _ ) ->
+                    ChangeToRound session (Just seq) clientState
+
+                -- the backend sends the rounds list after leaving a round
+                ( AvailableRounds _, OnRound _ ) ->
+                    ChangeToMenu session toClient
 
                 ( _, OnMenu _ ) ->
                     ForMenu <| Menu.gotEvent toClient
@@ -197,6 +229,14 @@ onEvent model value =
                 _ ->
                     CouldNotDecodeEvent
 
+        ( Ok (Api.UpgradeRequired { minVersion }), _ ) ->
+            if Api.protocolVersion < minVersion then
+                OutOfDate
+
+            else
+                -- still served, the next reload picks up the new version
+                UpgradeAvailable
+
         _ ->
             CouldNotDecodeEvent
 
@@ -214,7 +254,7 @@ subscriptions model =
             case model of
                 OnRound { clientState } ->
                     case clientState of
-                        Just (InLevel _) ->
+                        Just (InGame _) ->
                             Time.every 1000 (\_ -> ForRound Round.Tick)
 
                         _ ->
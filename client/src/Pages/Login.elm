@@ -1,6 +1,7 @@
 module Pages.Login exposing (Model, Msg(..), init, update, view)
 
 import Api exposing (LoginResponse(..), ToBackend(..))
+import Api.Extra
 import Html.Styled exposing (Html, button, div, input, label, text)
 import Html.Styled.Attributes exposing (type_, value)
 import Html.Styled.Events exposing (onClick, onInput)
@@ -59,18 +60,18 @@ update msg model =
             case httpResponse of
                 Ok loginResponse ->
                     case loginResponse of
-                        LoginSuccess { token } ->
+                        Success { token } ->
                             ( { model | username = token, loading = False }
                             , Cmd.none
                             )
 
-                        LoginFailure failure ->
+                        Failure failure ->
                             ( { model | msg = Just failure.msg, loading = False }
                             , Cmd.none
                             )
 
                 Err err ->
-                    ( { model | msg = Just <| Api.httpErrorToString err }
+                    ( { model | msg = Just <| Api.Extra.httpErrorToString err }
                     , Cmd.none
                     )
 
@@ -79,8 +80,8 @@ attemptLogin : String -> String -> Cmd Msg
 attemptLogin username password =
     Http.post
         { url = "/login"
-        , body = Http.jsonBody <| Api.loginEncoder <| { username = username, password = password }
-        , expect = Http.expectJson GotLoginResponse Api.decodeLoginResponse
+        , body = Http.jsonBody <| Api.encodeLogin <| { username = username, password = password, protocolVersion = Api.protocolVersion }
+        , expect = Http.expectJson GotLoginResponse Api.loginResponseDecoder
         }
 
 
@@ -1,15 +1,27 @@
 module Pages.Menu exposing (Model, Msg, dummy, gotEvent, init, toSession, update, view)
 
-import Api exposing (ClientState(..), RoundId, ToBackend(..), ToClient(..), ToClientEnvelope(..))
-import Html.Styled exposing (Html, button, div, li, text, ul)
-import Html.Styled.Events exposing (onClick)
+import Api exposing (ChatEntry, ClientState(..), RoundSummary, ToBackend(..), ToClient(..), ToClientEnvelope(..))
+import Api.Extra
+import Chat
+import Html.Styled exposing (Html, button, div, input, label, li, text, ul)
+import Html.Styled.Attributes exposing (checked, placeholder, type_, value)
+import Html.Styled.Events exposing (onCheck, onClick, onInput)
 import Http
 import Session exposing (Session)
 
 
 type alias Model =
     { session : Session
-    , roundIds : List RoundId
+    , rounds : List RoundSummary
+    , runningRounds : List RoundSummary
+    , decks : List String
+    , roundName : String
+    , password : String
+    , private : Bool
+    , inviteCode : String
+    , queuePosition : Maybe ( Int, Int )
+    , chat : List ChatEntry
+    , chatInput : String
     }
 
 
@@ -18,10 +30,19 @@ toSession =
     .session
 
 
-init : { a | token : b } -> { session : { token : b, username : String }, roundIds : List c }
+init : { a | token : b } -> { session : { token : b, username : String }, rounds : List c, runningRounds : List d, decks : List String, roundName : String, password : String, private : Bool, inviteCode : String, queuePosition : Maybe ( Int, Int ), chat : List ChatEntry, chatInput : String }
 init sessionData =
     { session = { token = sessionData.token, username = "placeholder" }
-    , roundIds = []
+    , rounds = []
+    , runningRounds = []
+    , decks = []
+    , roundName = ""
+    , password = ""
+    , private = False
+    , inviteCode = ""
+    , queuePosition = Nothing
+    , chat = []
+    , chatInput = ""
     }
 
 
@@ -29,6 +50,12 @@ type Msg
     = SendAction ToBackend
     | ActionSend (Result Http.Error ())
     | GotEvent ToClient
+    | SetRoundName String
+    | SetPassword String
+    | SetPrivate Bool
+    | SetInviteCode String
+    | SetChatInput String
+    | SendChat
 
 
 gotEvent : ToClient -> Msg
@@ -40,7 +67,7 @@ update : Msg -> Model -> ( Model, Cmd Msg )
 update msg model =
     case msg of
         SendAction toBackend ->
-            ( model, Api.sendAction ActionSend model.session.token toBackend )
+            ( model, Api.Extra.sendAction ActionSend model.session.token toBackend )
 
         GotEvent e ->
             ( fromBackend e model, Cmd.none )
@@ -48,30 +75,116 @@ update msg model =
         ActionSend _ ->
             ( model, Cmd.none )
 
+        SetRoundName roundName ->
+            ( { model | roundName = roundName }, Cmd.none )
+
+        SetPassword password ->
+            ( { model | password = password }, Cmd.none )
+
+        SetPrivate private ->
+            ( { model | private = private }, Cmd.none )
+
+        SetInviteCode inviteCode ->
+            ( { model | inviteCode = inviteCode }, Cmd.none )
+
+        SetChatInput chatInput ->
+            ( { model | chatInput = chatInput }, Cmd.none )
+
+        SendChat ->
+            ( { model | chatInput = "" }, Api.Extra.sendAction ActionSend model.session.token (Chat model.chatInput) )
+
 
 fromBackend : ToClient -> Model -> Model
 fromBackend toClient model =
     case toClient of
-        AvailableRounds { roundIds } ->
-            { model | roundIds = roundIds }
+        AvailableRounds { rounds, runningRounds, decks } ->
+            { model | rounds = rounds, runningRounds = runningRounds, decks = decks }
+
+        QueuePosition { position, queueLength } ->
+            { model | queuePosition = Maybe.map (\p -> ( p, queueLength )) position }
+
+        ChatMessage entry ->
+            { model | chat = Chat.append entry model.chat }
+
+        ServerNotice message ->
+            { model | chat = Chat.appendNotice message model.chat }
+
+        JoinFailed message ->
+            { model | chat = Chat.appendNotice message model.chat }
 
         _ ->
             model
 
 
 dummy =
-    { roundIds = [] }
+    { rounds = [], runningRounds = [], decks = [], roundName = "", password = "", private = False, inviteCode = "", queuePosition = Nothing, chat = [], chatInput = "" }
+
+
+nonEmpty : String -> Maybe String
+nonEmpty s =
+    if String.isEmpty s then
+        Nothing
+
+    else
+        Just s
 
 
-view : { a | roundIds : List RoundId } -> Html Msg
-view { roundIds } =
+view : { a | rounds : List RoundSummary, runningRounds : List RoundSummary, decks : List String, roundName : String, password : String, private : Bool, inviteCode : String, queuePosition : Maybe ( Int, Int ), chat : List ChatEntry, chatInput : String } -> Html Msg
+view { rounds, runningRounds, decks, roundName, password, private, inviteCode, queuePosition, chat, chatInput } =
     let
-        mkJoinRound roundId =
-            li [] [ button [ onClick <| SendAction <| JoinGame roundId ] [ text <| "join " ++ roundId ] ]
+        mkJoinRound round =
+            li []
+                [ text <| round.name ++ " by " ++ round.hostName
+                , text <| " (" ++ String.fromInt round.playerCount ++ "/" ++ String.fromInt round.capacity
+                , text <| " players, " ++ String.fromInt round.readyCount ++ " ready)"
+                , text <|
+                    if round.passwordProtected then
+                        " locked "
+
+                    else
+                        " "
+                , button [ onClick <| SendAction <| JoinGame { roundId = round.id, password = nonEmpty password } ] [ text "join" ]
+                ]
+
+        mkWatchRound round =
+            li []
+                [ text <| round.name ++ " by " ++ round.hostName
+                , text <| " (" ++ String.fromInt round.playerCount ++ " players) "
+                , button [ onClick <| SendAction <| Spectate round.id ] [ text "watch" ]
+                ]
+
+        startGame deck =
+            StartGame { deck = deck, seed = Nothing, name = nonEmpty roundName, password = nonEmpty password, private = private }
+
+        mkStartGame deck =
+            button [ onClick <| SendAction <| startGame (Just deck) ] [ text <| "start " ++ deck ++ " game" ]
+
+        quickPlay =
+            case queuePosition of
+                Just ( position, queueLength ) ->
+                    div []
+                        [ text <| "waiting for players, " ++ String.fromInt position ++ " of " ++ String.fromInt queueLength ++ " in the queue "
+                        , button [ onClick <| SendAction LeaveQueue ] [ text "leave queue" ]
+                        ]
+
+                Nothing ->
+                    div []
+                        [ button [ onClick <| SendAction <| QuickPlay Nothing ] [ text "quick play" ]
+                        , button [ onClick <| SendAction <| QuickPlay (Just 4) ] [ text "quick play with 4" ]
+                        ]
     in
     div []
         [ text "menu"
-        , button [ onClick <| SendAction StartGame ] [ text "start game" ]
+        , quickPlay
+        , input [ placeholder "round name", value roundName, onInput SetRoundName ] []
+        , input [ placeholder "password", type_ "password", value password, onInput SetPassword ] []
+        , label [] [ input [ type_ "checkbox", checked private, onCheck SetPrivate ] [], text "private" ]
+        , button [ onClick <| SendAction <| startGame Nothing ] [ text "start game" ]
+        , div [] <| List.map mkStartGame decks
         , button [ onClick <| SendAction GetAvailableRounds ] [ text "load rounds list" ]
-        , ul [] <| List.map mkJoinRound roundIds
+        , ul [] <| List.map mkJoinRound rounds
+        , ul [] <| List.map mkWatchRound runningRounds
+        , input [ placeholder "invite code", value inviteCode, onInput SetInviteCode ] []
+        , button [ onClick <| SendAction <| JoinByInviteCode inviteCode ] [ text "join with code" ]
+        , Chat.view { chat = chat, chatInput = chatInput } SetChatInput SendChat SendAction
         ]
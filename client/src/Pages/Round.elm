@@ -8,7 +8,9 @@ module Pages.Round exposing
     , view
     )
 
-import Api exposing (ClientState(..), ToBackend(..), ToClient(..), ToClientEnvelope(..), sendAction)
+import Api exposing (ChatEntry, ClientState(..), ControlType(..), ToBackend(..), ToClient(..), ToClientEnvelope(..))
+import Api.Extra exposing (sendAction)
+import Chat
 import Html.Styled exposing (Html, button, div, li, p, span, text, ul)
 import Html.Styled.Attributes exposing (style)
 import Html.Styled.Events exposing (onClick)
@@ -20,7 +22,12 @@ type alias Model =
     { session : Session
     , events : List ToClient
     , clientState : Maybe ClientState
+
+    -- of the last GameSnapshot or GameDelta, the next delta builds on it
+    , seq : Maybe Int
     , instructionOpacity : Float
+    , chat : List ChatEntry
+    , chatInput : String
     }
 
 
@@ -31,14 +38,22 @@ toSession =
 
 updateClientState : Session -> ClientState -> Maybe Model -> Model
 updateClientState session clientState mbOldState =
+    let
+        chat =
+            Maybe.withDefault [] <| Maybe.map .chat mbOldState
+
+        chatInput =
+            Maybe.withDefault "" <| Maybe.map .chatInput mbOldState
+    in
     case ( clientState, mbOldState ) of
-        ( InLevel newClientState, Just oldModel ) ->
+        ( InGame newClientState, Just oldModel ) ->
             { session = session
             , events = []
             , clientState = Just clientState
+            , seq = Nothing
             , instructionOpacity =
                 case oldModel.clientState of
-                    Just (InLevel cs) ->
+                    Just (InGame cs) ->
                         if newClientState.currentInstruction == cs.currentInstruction then
                             oldModel.instructionOpacity
 
@@ -47,13 +62,18 @@ updateClientState session clientState mbOldState =
 
                     _ ->
                         1.0
+            , chat = chat
+            , chatInput = chatInput
             }
 
         _ ->
             { session = session
             , events = []
             , clientState = Just clientState
+            , seq = Nothing
             , instructionOpacity = 1.0
+            , chat = chat
+            , chatInput = chatInput
             }
 
 
@@ -68,6 +88,8 @@ type Msg
     | GotEvent ToClient
     | SendAction ToBackend
     | Tick
+    | SetChatInput String
+    | SendChat
 
 
 update : Msg -> Model -> ( Model, Cmd Msg )
@@ -84,20 +106,55 @@ update msg model =
         EventDecoderError e ->
             ( Debug.log e model, Cmd.none )
 
+        GotEvent (GameSnapshot { seq, clientState }) ->
+            let
+                model_ =
+                    updateClientState model.session clientState (Just model)
+            in
+            ( { model_ | seq = Just seq }, Cmd.none )
+
+        GotEvent (GameDelta { seq, delta }) ->
+            case ( model.clientState, model.seq ) of
+                ( Just (InGame inGame), Just lastSeq ) ->
+                    if seq == lastSeq + 1 then
+                        let
+                            model_ =
+                                updateClientState model.session (InGame (applyDelta delta inGame)) (Just model)
+                        in
+                        ( { model_ | seq = Just seq }, Cmd.none )
+
+                    else
+                        resync model
+
+                _ ->
+                    resync model
+
         GotEvent e ->
             let
                 newClientState =
                     case e of
-                        UpdateGameState { clientState } ->
+                        UpdateGameState clientState ->
                             Just clientState
 
                         _ ->
                             model.clientState
 
+                newChat =
+                    case e of
+                        ChatMessage entry ->
+                            Chat.append entry model.chat
+
+                        ServerNotice message ->
+                            Chat.appendNotice message model.chat
+
+                        _ ->
+                            model.chat
+
                 model_ =
                     { model
                         | events = e :: model.events
                         , clientState = newClientState
+                        , chat = newChat
                     }
             in
             ( model_, Cmd.none )
@@ -105,6 +162,37 @@ update msg model =
         SendAction toBackend ->
             ( model, sendAction (\_ -> NoOp) model.session.token toBackend )
 
+        SetChatInput chatInput ->
+            ( { model | chatInput = chatInput }, Cmd.none )
+
+        SendChat ->
+            ( { model | chatInput = "" }, sendAction (\_ -> NoOp) model.session.token (Chat model.chatInput) )
+
+
+-- a delta went missing, the backend sends the whole state again
+resync : Model -> ( Model, Cmd Msg )
+resync model =
+    ( { model | seq = Nothing }, sendAction (\_ -> NoOp) model.session.token Resync )
+
+
+applyDelta : Api.InGameDelta -> Api.InGameDetails -> Api.InGameDetails
+applyDelta delta inGame =
+    let
+        applyItemState item =
+            List.filter (\itemState -> itemState.id == item.id) delta.itemStates
+                |> List.head
+                |> Maybe.map (\itemState -> { item | state = itemState.state })
+                |> Maybe.withDefault item
+    in
+    { inGame
+        | currentInstruction = Maybe.withDefault inGame.currentInstruction delta.currentInstruction
+        , uiItems = List.map applyItemState inGame.uiItems
+        , instructionsExecuted = Maybe.withDefault inGame.instructionsExecuted delta.instructionsExecuted
+        , instructionsMissed = Maybe.withDefault inGame.instructionsMissed delta.instructionsMissed
+        , levelProgress = Maybe.withDefault inGame.levelProgress delta.levelProgress
+        , levelTarget = Maybe.withDefault inGame.levelTarget delta.levelTarget
+    }
+
 
 view : Model -> Html Msg
 view model =
@@ -116,29 +204,104 @@ view model =
 
             Just state ->
                 viewGame state model.instructionOpacity
+        , Chat.view { chat = model.chat, chatInput = model.chatInput } SetChatInput SendChat SendAction
         ]
 
 
-mkUiItem : Api.UiItem -> Html Msg
-mkUiItem { label, state, id, maxValue } =
+mkUiItem : Api.ClientUiItem -> Html Msg
+mkUiItem { label, state, id, controlType, maxValue } =
     case List.head <| List.drop (maxValue - 2) Util.knobDefinitions of
         Nothing ->
             text "knob not found"
 
         Just knobDefinition ->
-            li [ onClick <| SendAction <| ChangeSetting id (modBy maxValue (state + 1)) ]
-                [ text label
-                , text <| " ( " ++ String.fromInt state
-                , text "/"
-                , text <| String.fromInt maxValue ++ " ) "
-                , Util.knob knobDefinition state
-                ]
+            case controlType of
+                Slider ->
+                    li []
+                        [ text label
+                        , Util.slider knobDefinition state (\value -> SendAction <| ChangeSetting { itemId = id, value = value })
+                        ]
+
+                _ ->
+                    li [ onClick <| SendAction <| ChangeSetting { itemId = id, value = modBy maxValue (state + 1) } ]
+                        [ text label
+                        , text <| " ( " ++ String.fromInt state
+                        , text "/"
+                        , text <| String.fromInt maxValue ++ " ) "
+                        , Util.knob knobDefinition state
+                        ]
 
 
 viewGame : ClientState -> Float -> Html Msg
 viewGame client_state opacity =
     case client_state of
-        Lobby { playerCount, playerReadyCount } ->
+        Lobby { playerCount, playerReadyCount, players, host, inviteCode, difficulty, capacity, tickInterval, locked } ->
+            let
+                mkPlayer player =
+                    li []
+                        [ text player.username
+                        , text <|
+                            if player.ready then
+                                " (ready)"
+
+                            else
+                                ""
+                        , if host then
+                            button [ onClick <| SendAction <| Kick player.userId ] [ text "kick" ]
+
+                          else
+                            text ""
+                        ]
+
+                noSettings =
+                    { difficulty = Nothing, maxPlayers = Nothing, tickInterval = Nothing }
+
+                mkDifficulty d =
+                    button [ onClick <| SendAction <| UpdateRoundSettings { noSettings | difficulty = Just d } ]
+                        [ text <| Api.difficultyToString d ]
+
+                settings =
+                    p [] <|
+                        [ text <| Api.difficultyToString difficulty
+                        , text <| ", up to " ++ String.fromInt capacity ++ " players"
+                        , text <| ", a tick every " ++ String.fromInt tickInterval ++ "s"
+                        , text <|
+                            if locked then
+                                ", locked "
+
+                            else
+                                " "
+                        ]
+                            ++ (if host then
+                                    List.map mkDifficulty [ Api.Easy, Api.Normal, Api.Hard ]
+                                        ++ [ button [ onClick <| SendAction <| UpdateRoundSettings { noSettings | maxPlayers = Just (capacity - 1) } ] [ text "fewer players" ]
+                                           , button [ onClick <| SendAction <| UpdateRoundSettings { noSettings | maxPlayers = Just (capacity + 1) } ] [ text "more players" ]
+                                           , button [ onClick <| SendAction <| UpdateRoundSettings { noSettings | tickInterval = Just (tickInterval - 1) } ] [ text "faster" ]
+                                           , button [ onClick <| SendAction <| UpdateRoundSettings { noSettings | tickInterval = Just (tickInterval + 1) } ] [ text "slower" ]
+                                           , button [ onClick <| SendAction <| LockLobby (not locked) ]
+                                                [ text <|
+                                                    if locked then
+                                                        "unlock"
+
+                                                    else
+                                                        "lock"
+                                                ]
+                                           , button [ onClick <| SendAction ForceStart ] [ text "start now" ]
+                                           ]
+
+                                else
+                                    []
+                               )
+
+                inviteControls =
+                    if host then
+                        [ button [ onClick <| SendAction RegenerateInviteCode ] [ text "new invite code" ]
+                        , button [ onClick <| SendAction RevokeInviteCode ] [ text "revoke invite code" ]
+                        ]
+
+                    else
+                        []
+            in
             div []
                 [ text "players "
                 , text <| String.fromInt playerReadyCount
@@ -146,11 +309,21 @@ viewGame client_state opacity =
                 , text <| String.fromInt playerCount
                 , text " are ready"
                 , button [ onClick <| SendAction ToggleReady ] [ text "Ready" ]
+                , button [ onClick <| SendAction LeaveRound ] [ text "Leave" ]
+                , ul [] <| List.map mkPlayer players
+                , settings
+                , p [] <|
+                    text ("invite code: " ++ Maybe.withDefault "none" inviteCode)
+                        :: inviteControls
                 ]
 
-        InLevel { currentInstruction, uiItems, instructionsExecuted, instructionsMissed } ->
+        InGame { currentInstruction, uiItems, instructionsExecuted, instructionsMissed, level, levelCount, levelProgress, levelTarget } ->
             div []
-                [ p [] [ text "Instructions executed: ", text <| String.fromInt instructionsExecuted ]
+                [ p []
+                    [ text <| "Level " ++ String.fromInt level ++ " of " ++ String.fromInt levelCount
+                    , text <| " ( " ++ String.fromInt levelProgress ++ "/" ++ String.fromInt levelTarget ++ " )"
+                    ]
+                , p [] [ text "Instructions executed: ", text <| String.fromInt instructionsExecuted ]
                 , p [] [ text "Instructions missed: ", text <| String.fromInt instructionsMissed ]
                 , p []
                     [ text "instruction:"
@@ -158,4 +331,46 @@ viewGame client_state opacity =
                         [ text currentInstruction ]
                     ]
                 , ul [] <| List.map mkUiItem uiItems
+                , button [ onClick <| SendAction LeaveRound ] [ text "Leave" ]
+                ]
+
+        Finished { levelsCompleted, instructionsExecuted, instructionsMissed } ->
+            div []
+                [ p [] [ text "All levels completed: ", text <| String.fromInt levelsCompleted ]
+                , p [] [ text "Instructions executed: ", text <| String.fromInt instructionsExecuted ]
+                , p [] [ text "Instructions missed: ", text <| String.fromInt instructionsMissed ]
+                , button [ onClick <| SendAction LeaveRound ] [ text "Back to menu" ]
+                ]
+
+        Spectating { players, instructionsExecuted, instructionsMissed, level, levelCount, levelProgress, levelTarget, finished } ->
+            let
+                -- read only, unlike mkUiItem
+                mkSpectatedItem { label, state, maxValue } =
+                    li [] [ text <| label ++ " ( " ++ String.fromInt state ++ "/" ++ String.fromInt maxValue ++ " )" ]
+
+                mkPlayer player =
+                    li []
+                        [ text player.username
+                        , p [] [ text <| "instruction: " ++ Maybe.withDefault "-" player.currentInstruction ]
+                        , ul [] <| List.map mkSpectatedItem player.uiItems
+                        ]
+            in
+            div []
+                [ p []
+                    [ text "spectating, "
+                    , text <|
+                        if finished then
+                            "round finished"
+
+                        else if level == 0 then
+                            "waiting for the players"
+
+                        else
+                            "level " ++ String.fromInt level ++ " of " ++ String.fromInt levelCount
+                    , text <| " ( " ++ String.fromInt levelProgress ++ "/" ++ String.fromInt levelTarget ++ " )"
+                    ]
+                , p [] [ text "Instructions executed: ", text <| String.fromInt instructionsExecuted ]
+                , p [] [ text "Instructions missed: ", text <| String.fromInt instructionsMissed ]
+                , ul [] <| List.map mkPlayer players
+                , button [ onClick <| SendAction LeaveRound ] [ text "Leave" ]
                 ]
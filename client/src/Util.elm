@@ -1,8 +1,9 @@
 module Util exposing (..)
 
-import Css exposing (backgroundImage, backgroundPosition, backgroundPosition2, height, px, width)
+import Css exposing (backgroundImage, backgroundPosition, backgroundPosition2, cursor, displayFlex, height, pointer, px, width)
 import Html.Styled exposing (Html, div)
 import Html.Styled.Attributes exposing (css)
+import Html.Styled.Events exposing (onClick)
 
 
 type alias KnobDefinition =
@@ -37,3 +38,29 @@ knob { filename, w, h } value =
             ]
         ]
         []
+
+
+slider : KnobDefinition -> Int -> (Int -> msg) -> Html msg
+slider { filename, maxValue, w, h } value onSelect =
+    let
+        segment index =
+            div
+                [ css
+                    [ width <| px <| toFloat w / toFloat maxValue
+                    , height <| px <| toFloat h
+                    , cursor pointer
+                    ]
+                , onClick <| onSelect index
+                ]
+                []
+    in
+    div
+        [ css
+            [ width <| px <| toFloat w
+            , height <| px <| toFloat h
+            , displayFlex
+            , backgroundImage <| Css.url <| "/assets/knobs/" ++ filename
+            , backgroundPosition2 (px 0) (px <| toFloat (-h * value))
+            ]
+        ]
+        (List.map segment <| List.range 0 (maxValue - 1))
@@ -0,0 +1,35 @@
+CREATE TYPE round_outcome AS ENUM ('completed', 'ended', 'abandoned');
+
+CREATE TABLE rounds (
+    id TEXT PRIMARY KEY,
+    deck TEXT NOT NULL,
+    seed BIGINT NOT NULL,
+    level INTEGER NOT NULL,
+    instructions_executed INTEGER NOT NULL,
+    instructions_missed INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ,
+    -- how a round came to an end, NULL while it's still going
+    outcome round_outcome
+);
+
+-- the rounds restored after a restart, oldest first
+CREATE INDEX rounds_unfinished ON rounds (created_at) WHERE outcome IS NULL;
+
+-- each round's log, appended to as it goes on and replayed to restore it
+CREATE TABLE round_events (
+    round_id TEXT NOT NULL REFERENCES rounds (id) ON DELETE CASCADE,
+    -- the event's position in the round's log, from 0
+    seq INTEGER NOT NULL,
+    event JSONB NOT NULL,
+    PRIMARY KEY (round_id, seq)
+);
+
+CREATE TABLE round_participants (
+    round_id TEXT NOT NULL REFERENCES rounds (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id),
+    PRIMARY KEY (round_id, user_id)
+);
+
+CREATE INDEX round_participants_user_id ON round_participants (user_id);
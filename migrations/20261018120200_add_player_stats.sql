@@ -0,0 +1,10 @@
+ALTER TABLE rounds ADD COLUMN started_at TIMESTAMPTZ;
+
+ALTER TABLE round_participants
+    ADD COLUMN changes INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN hits INTEGER NOT NULL DEFAULT 0,
+    -- sum of milliseconds between an instruction showing up and the change completing it
+    ADD COLUMN reaction_ms_total BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN reactions INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX rounds_started_at ON rounds (started_at);
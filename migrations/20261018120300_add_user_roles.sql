@@ -0,0 +1,4 @@
+CREATE TYPE user_role AS ENUM ('player', 'moderator', 'admin');
+
+-- promote with e.g. UPDATE users SET role = 'admin' WHERE username = '...'
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'player';
@@ -0,0 +1,144 @@
+use serde::Deserialize;
+use tracing::{info, warn};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    app::{RoundId, ToClient},
+    auth::{handle_rejection, with_role},
+    env::Env,
+    user::{Role, User, UserId},
+    with_env,
+};
+
+#[derive(Deserialize)]
+struct Broadcast {
+    message: String,
+}
+
+// Operator endpoints under /admin, they take `Authorization: Bearer <token>`
+// of a logged in moderator or admin
+pub fn routes(env: Env) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let sessions = warp::get()
+        .and(warp::path!("sessions"))
+        .and(with_role(env.clone(), Role::Admin))
+        .and(with_env(env.clone()))
+        .and_then(sessions_handler);
+    let rounds = warp::get()
+        .and(warp::path!("rounds"))
+        .and(with_role(env.clone(), Role::Moderator))
+        .and(with_env(env.clone()))
+        .and_then(rounds_handler);
+    let end_round = warp::post()
+        .and(warp::path!("rounds" / String / "end"))
+        .and(with_role(env.clone(), Role::Moderator))
+        .and(with_env(env.clone()))
+        .and_then(end_round_handler);
+    let delete_round = warp::delete()
+        .and(warp::path!("rounds" / String))
+        .and(with_role(env.clone(), Role::Admin))
+        .and(with_env(env.clone()))
+        .and_then(delete_round_handler);
+    let kick = warp::post()
+        .and(warp::path!("users" / i32 / "kick"))
+        .and(with_role(env.clone(), Role::Moderator))
+        .and(with_env(env.clone()))
+        .and_then(kick_handler);
+    let broadcast = warp::post()
+        .and(warp::path!("broadcast"))
+        .and(with_role(env.clone(), Role::Admin))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::json())
+        .and(with_env(env))
+        .and_then(broadcast_handler);
+
+    warp::path("admin")
+        .and(
+            sessions
+                .or(rounds)
+                .or(end_round)
+                .or(delete_round)
+                .or(kick)
+                .or(broadcast),
+        )
+        .recover(handle_rejection)
+}
+
+async fn sessions_handler(_admin: User, env: Env) -> Result<impl Reply, Rejection> {
+    let sessions = env.client_broadcaster.sessions().await;
+    Ok(warp::reply::json(&sessions))
+}
+
+async fn rounds_handler(_moderator: User, env: Env) -> Result<impl Reply, Rejection> {
+    let rounds = env.app.round_overviews().await;
+    Ok(warp::reply::json(&rounds))
+}
+
+async fn end_round_handler(
+    round_id: RoundId,
+    moderator: User,
+    env: Env,
+) -> Result<impl Reply, Rejection> {
+    info!("User {:?} ends round {:?}", moderator.id, round_id);
+    match env.app.end_round(&round_id).await {
+        Some(msgs) => {
+            for client_message in msgs {
+                env.client_broadcaster.send_to_user(client_message).await;
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn delete_round_handler(
+    round_id: RoundId,
+    admin: User,
+    env: Env,
+) -> Result<impl Reply, Rejection> {
+    info!("User {:?} deletes round {:?}", admin.id, round_id);
+    match env.app.delete_round(&round_id).await {
+        Some(msgs) => {
+            for client_message in msgs {
+                env.client_broadcaster.send_to_user(client_message).await;
+            }
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Ok(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn kick_handler(user_id: UserId, moderator: User, env: Env) -> Result<impl Reply, Rejection> {
+    let user = match env.user_service.find_user(user_id).await {
+        Some(user) => user,
+        None => return Ok(StatusCode::NOT_FOUND),
+    };
+    // nobody kicks their peers or superiors
+    if user.role >= moderator.role {
+        warn!(
+            "User {:?} can't kick {:?}, a {:?}",
+            moderator.id, user_id, user.role
+        );
+        return Ok(StatusCode::FORBIDDEN);
+    }
+    info!("User {:?} kicks user {:?}", moderator.id, user_id);
+    for client_message in env.app.kick(&user).await {
+        env.client_broadcaster.send_to_user(client_message).await;
+    }
+    let revoked = env.client_broadcaster.revoke_user(user_id).await;
+    info!("Revoked {:?} sessions of user {:?}", revoked, user_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn broadcast_handler(
+    admin: User,
+    broadcast: Broadcast,
+    env: Env,
+) -> Result<impl Reply, Rejection> {
+    info!("User {:?} broadcasts {:?}", admin.id, broadcast.message);
+    env.client_broadcaster
+        .broadcast(ToClient::ServerNotice {
+            message: broadcast.message,
+        })
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
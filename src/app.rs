@@ -1,66 +1,416 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use log::{error, info, warn};
-use rand::{prelude::SliceRandom, thread_rng, Rng};
+use rand::{prelude::SliceRandom, thread_rng, Rng, SeedableRng};
+use rand_pcg::Pcg32;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use sha2::{Digest, Sha256};
 use tokio::sync::RwLock;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use crate::user::{User, UserId};
-
-const ITEMS: &'static [&'static str] = &[
-    "Chemex Coffeemaker",
-    "Sound system",
-    "Pizza oven",
-    "Foot massager",
-    "Heating",
-    "Radio",
-    "Windshield wiper",
-    "Flux compensator",
-    "Warp Core",
-    "Fridge",
-    "Fireplace",
-    "Ventilation",
+use crate::{
+    catalog::{CatalogItem, ItemCatalog},
+    chat::{
+        clean_text, within_rate_limit, ChatBody, ChatEntry, ChatFilter, Emote, CHAT_HISTORY_LENGTH,
+        CHAT_RATE_LIMIT, CHAT_RATE_WINDOW_MS,
+    },
+    delta::InGameDelta,
+    rounds::RoundOutcome,
+    user::{Role, User, UserId},
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum ControlType {
+    Switch,
+    Dial,
+    Slider,
+}
+
+impl ControlType {
+    // the client only has knob sprites for these step counts
+    pub fn max_values(&self) -> &'static [u8] {
+        match self {
+            ControlType::Switch => &[2],
+            ControlType::Dial => &[3, 6, 7, 8, 10],
+            ControlType::Slider => &[4, 5, 9],
+        }
+    }
+
+    pub fn default_instruction(&self) -> &'static str {
+        match self {
+            ControlType::Switch => "Switch {label} {value}",
+            ControlType::Dial => "Turn {label} to {value}",
+            ControlType::Slider => "Set {label} to {value}",
+        }
+    }
+}
+
+struct LevelConfig {
+    items_per_player: usize,
+    instruction_ttl: i32,
+    instructions_per_player: usize,
+    control_types: &'static [ControlType],
+}
+
+const LEVELS: &[LevelConfig] = &[
+    LevelConfig {
+        items_per_player: 3,
+        instruction_ttl: 6,
+        instructions_per_player: 3,
+        control_types: &[ControlType::Switch],
+    },
+    LevelConfig {
+        items_per_player: 4,
+        instruction_ttl: 5,
+        instructions_per_player: 4,
+        control_types: &[ControlType::Switch, ControlType::Dial],
+    },
+    LevelConfig {
+        items_per_player: 5,
+        instruction_ttl: 4,
+        instructions_per_player: 5,
+        control_types: &[ControlType::Switch, ControlType::Dial, ControlType::Slider],
+    },
+    LevelConfig {
+        items_per_player: 6,
+        instruction_ttl: 3,
+        instructions_per_player: 6,
+        control_types: &[ControlType::Switch, ControlType::Dial, ControlType::Slider],
+    },
 ];
 
 pub struct Model {
     pub games_by_id: HashMap<RoundId, RocketJamRound>,
     pub game_ids_by_user_id: HashMap<UserId, RoundId>,
+    pub round_logs: HashMap<RoundId, Vec<RoundEvent>>,
+    // how many events of each round's log were handed out to be saved
+    pub persisted_event_counts: HashMap<RoundId, usize>,
+    // rounds changed since they were last persisted
+    pub dirty_round_ids: HashSet<RoundId>,
+    // rounds everybody left before they started, to be removed from the database
+    pub deleted_round_ids: HashSet<RoundId>,
+    // started rounds everybody left, to be saved one last time
+    pub closed_rounds: Vec<RoundSnapshot>,
+    // users looking at the rounds list, they get pushed updates of it
+    pub menu_user_ids: HashSet<UserId>,
+    // quick play players waiting for a round, longest waiting first
+    pub queue: Vec<QueueEntry>,
+    // the latest messages of each channel, oldest first
+    pub chats: HashMap<ChatChannel, Vec<ChatEntry>>,
+    pub chat_sent_at_ms: HashMap<UserId, Vec<u64>>,
+    pub invite_code_tried_at_ms: HashMap<UserId, Vec<u64>>,
     pub tick: i32,
 }
 
+// Round members chat within their round, everybody on the menu in the lobby
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChatChannel {
+    Lobby,
+    Round(RoundId),
+}
+
+#[derive(Clone, Debug)]
+pub struct QueueEntry {
+    user_id: UserId,
+    username: String,
+    preferred_size: usize,
+    queued_at_ms: u64,
+}
+
+// Everything that changes a round, in the order it happened. Replaying the
+// events of a round rebuilds it exactly, see `replay`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RoundEvent {
+    Created {
+        round_id: Uuid,
+        user_id: UserId,
+        deck: String,
+        seed: u64,
+        username: String,
+        name: String,
+        // see `hash_password`, the password itself is never stored
+        password_hash: Option<String>,
+        created_at_ms: u64,
+        private: bool,
+        invite_code: Option<String>,
+        // instruction lifetimes scale with it
+        tick_interval: i32,
+    },
+    // codes are drawn outside the round, its rng state is public in replays
+    InviteCodeChanged {
+        user_id: UserId,
+        invite_code: Option<String>,
+    },
+    Joined {
+        user_id: UserId,
+        username: String,
+    },
+    SpectatorJoined {
+        user_id: UserId,
+        username: String,
+    },
+    Action {
+        user_id: UserId,
+        tick: i32,
+        at_ms: u64,
+        action: ToBackend,
+        // a moderator acting, who may do what the host may
+        host_override: bool,
+    },
+    Tick {
+        tick: i32,
+        at_ms: u64,
+    },
+    // an operator ended the round, see the admin API
+    Ended {
+        at_ms: u64,
+    },
+}
+
+// The game time events happen at, round logic never reads the system clock
+#[derive(Clone, Copy, Debug)]
+struct Clock {
+    tick: i32,
+    now_ms: u64,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReplayStep {
+    seq: usize,
+    event: RoundEvent,
+    round: RocketJamRound,
+}
+
+// A page of a round's replay, the states only of the events on it
+#[derive(Serialize, Clone, Debug)]
+pub struct RoundReplay {
+    round_id: RoundId,
+    event_count: usize,
+    offset: usize,
+    steps: Vec<ReplayStep>,
+    pub finished: bool,
+    // false means the live round diverged from its own event log, None
+    // once there's no live round anymore
+    matches_live_round: Option<bool>,
+}
+
+pub const REPLAY_PAGE_SIZE: usize = 100;
+pub const MAX_REPLAY_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ReplayQuery {
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ToClient {
     HelloClient,
-    UpdateGameState { client_state: ClientState },
-    AvailableRounds { round_ids: Vec<RoundId> },
-    EnterRound { client_state: ClientState },
+    UpdateGameState {
+        client_state: ClientState,
+    },
+    AvailableRounds {
+        rounds: Vec<RoundSummary>,
+        // public rounds already playing, they can only be watched
+        running_rounds: Vec<RoundSummary>,
+        decks: Vec<String>,
+    },
+    EnterRound {
+        client_state: ClientState,
+    },
+    // position is 1 based, None once the user isn't queued anymore
+    QueuePosition {
+        position: Option<usize>,
+        queue_length: usize,
+    },
+    MatchFound {
+        round_id: RoundId,
+    },
+    ChatMessage {
+        message: ChatEntry,
+    },
+    // e.g. a maintenance announcement, sent to everybody connected
+    ServerNotice {
+        message: String,
+    },
+    // the round couldn't be joined, e.g. for a wrong password
+    JoinFailed {
+        message: String,
+    },
+    // what clients from protocol 3 on get instead of UpdateGameState in a
+    // level, deltas with the following seqs build on it, see delta.rs
+    GameSnapshot {
+        seq: u64,
+        client_state: ClientState,
+    },
+    // what changed since the state numbered `seq - 1`
+    GameDelta {
+        seq: u64,
+        delta: InGameDelta,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoundSummary {
+    id: RoundId,
+    name: String,
+    host_name: String,
+    player_count: usize,
+    capacity: usize,
+    ready_count: usize,
+    created_at_ms: u64,
+    password_protected: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ToBackend {
     Init,
-    StartGame,
+    StartGame {
+        deck: Option<String>,
+        #[serde(default)]
+        seed: Option<u64>,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        private: bool,
+    },
     ToggleReady,
-    ChangeSetting { item_id: ItemId, value: u8 },
+    ChangeSetting {
+        item_id: ItemId,
+        value: u8,
+    },
     GetAvailableRounds,
-    JoinGame { round_id: RoundId },
+    JoinGame {
+        round_id: RoundId,
+        #[serde(default)]
+        password: Option<String>,
+    },
+    LeaveRound,
+    JoinByInviteCode {
+        invite_code: String,
+    },
+    RegenerateInviteCode,
+    RevokeInviteCode,
+    Kick {
+        user_id: UserId,
+    },
+    UpdateRoundSettings {
+        #[serde(default)]
+        difficulty: Option<Difficulty>,
+        #[serde(default)]
+        max_players: Option<usize>,
+        #[serde(default)]
+        tick_interval: Option<i32>,
+    },
+    LockLobby {
+        locked: bool,
+    },
+    ForceStart,
+    QuickPlay {
+        #[serde(default)]
+        preferred_size: Option<usize>,
+    },
+    LeaveQueue,
+    Spectate {
+        round_id: RoundId,
+    },
+    Chat {
+        text: String,
+    },
+    Emote {
+        emote: Emote,
+    },
+    // the client missed a delta, it gets its state in full again
+    Resync,
+}
+
+impl ToBackend {
+    // the variant's name, e.g. "ChangeSetting", never any of its contents
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToBackend::Init => "Init",
+            ToBackend::StartGame { .. } => "StartGame",
+            ToBackend::ToggleReady => "ToggleReady",
+            ToBackend::ChangeSetting { .. } => "ChangeSetting",
+            ToBackend::GetAvailableRounds => "GetAvailableRounds",
+            ToBackend::JoinGame { .. } => "JoinGame",
+            ToBackend::LeaveRound => "LeaveRound",
+            ToBackend::JoinByInviteCode { .. } => "JoinByInviteCode",
+            ToBackend::RegenerateInviteCode => "RegenerateInviteCode",
+            ToBackend::RevokeInviteCode => "RevokeInviteCode",
+            ToBackend::Kick { .. } => "Kick",
+            ToBackend::UpdateRoundSettings { .. } => "UpdateRoundSettings",
+            ToBackend::LockLobby { .. } => "LockLobby",
+            ToBackend::ForceStart => "ForceStart",
+            ToBackend::QuickPlay { .. } => "QuickPlay",
+            ToBackend::LeaveQueue => "LeaveQueue",
+            ToBackend::Spectate { .. } => "Spectate",
+            ToBackend::Chat { .. } => "Chat",
+            ToBackend::Emote { .. } => "Emote",
+            ToBackend::Resync => "Resync",
+        }
+    }
+
+    // what may go into the log of the sender's round, the rest is about
+    // other rounds or the menu and may carry their passwords and codes
+    fn is_round_action(&self) -> bool {
+        matches!(
+            self,
+            ToBackend::ToggleReady
+                | ToBackend::ChangeSetting { .. }
+                | ToBackend::LeaveRound
+                | ToBackend::Kick { .. }
+                | ToBackend::UpdateRoundSettings { .. }
+                | ToBackend::LockLobby { .. }
+                | ToBackend::ForceStart
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum RocketJam {
     InLobby { players_ready: Vec<UserId> },
     InLevel(RoundState),
+    Finished(RoundState),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct RoundState {
-    available_items: Vec<(ItemId, String, u8)>,
+    level: usize,
     items: Vec<Item>,
     instructions: Vec<Instruction>,
+    level_instructions_executed: usize,
+    level_instructions_required: usize,
     instructions_executed: usize,
     instructions_missed: usize,
+    player_stats: HashMap<UserId, PlayerStats>,
+    // the last level was played through, rather than the round ended early
+    #[serde(default)]
+    completed: bool,
+}
+
+// Per player counters over the whole round, they carry over between levels
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerStats {
+    pub changes: usize,
+    pub hits: usize,
+    pub reaction_ms_total: u64,
+    pub reactions: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -69,23 +419,161 @@ pub struct Instruction {
     item_id: ItemId,
     state: u8,
     eol_tick: i32,
+    issued_at_ms: u64,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RocketJamRound {
     id: Uuid,
+    seed: u64,
+    // every random decision of the round is drawn from here, so a seed replays the round
+    rng: Pcg32,
+    deck: String,
+    // unique and in joining order, which decides how items are dealt
     players: Vec<UserId>,
+    // watch the round without taking part, they never get items or instructions
+    #[serde(default)]
+    spectators: Vec<UserId>,
+    capacity: usize,
+    name: String,
+    host: UserId,
+    player_names: HashMap<UserId, String>,
+    password_hash: Option<String>,
+    created_at_ms: u64,
+    // private rounds aren't listed and can only be joined with the invite code
+    private: bool,
+    invite_code: Option<String>,
+    // settings the host can change in the lobby
+    difficulty: Difficulty,
+    // game loop ticks between two ticks of this round
+    tick_interval: i32,
+    locked: bool,
     game: RocketJam,
 }
 
-type ItemId = usize;
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+const DEFAULT_CAPACITY: usize = 6;
+const MAX_CAPACITY: usize = 8;
+const DEFAULT_TICK_INTERVAL: i32 = 3;
+const MAX_TICK_INTERVAL: i32 = 5;
+const MIN_MATCH_SIZE: usize = 2;
+// after waiting this long a queued player takes a round of any size
+const MATCH_WAIT_MS: u64 = 20_000;
+const MAX_ROUND_NAME_LENGTH: usize = 40;
+const MAX_SPECTATORS: usize = 20;
+const INVITE_CODE_WORDS: &[&str] = &[
+    "ROCKET", "COMET", "ORBIT", "NOVA", "LASER", "PULSAR", "METEOR", "NEBULA", "GALAXY", "APOLLO",
+];
+// no I or O, they read like 1 and 0
+const INVITE_CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+// codes tried per user within the window, so they can't be guessed
+const INVITE_CODE_TRY_LIMIT: usize = 5;
+const INVITE_CODE_TRY_WINDOW_MS: u64 = 60_000;
+// with that many rounds open the code space is full enough to give up
+const MAX_INVITE_CODE_ATTEMPTS: usize = 10;
+
+enum JoinTarget {
+    Round {
+        round_id: RoundId,
+        password: Option<String>,
+    },
+    InviteCode(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum JoinError {
+    AlreadyJoined,
+    Full,
+    AlreadyStarted,
+    Locked,
+}
+
+impl RocketJamRound {
+    fn summary(&self) -> RoundSummary {
+        let ready_count = match &self.game {
+            RocketJam::InLobby { players_ready } => players_ready.len(),
+            _ => self.players.len(),
+        };
+        RoundSummary {
+            id: self.id.to_string(),
+            name: self.name.clone(),
+            host_name: self
+                .player_names
+                .get(&self.host)
+                .cloned()
+                .unwrap_or_default(),
+            player_count: self.players.len(),
+            capacity: self.capacity,
+            ready_count,
+            created_at_ms: self.created_at_ms,
+            password_protected: self.password_hash.is_some(),
+        }
+    }
+
+    // rounds without a password let anybody in
+    fn password_matches(&self, password: Option<&str>) -> bool {
+        match (&self.password_hash, password) {
+            (None, _) => true,
+            (Some(hash), Some(password)) => *hash == hash_password(&self.id, password),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn can_join(&self, user_id: UserId) -> Result<(), JoinError> {
+        if self.is_member(user_id) {
+            Err(JoinError::AlreadyJoined)
+        } else if self.players.len() >= self.capacity {
+            Err(JoinError::Full)
+        } else if !matches!(self.game, RocketJam::InLobby { .. }) {
+            Err(JoinError::AlreadyStarted)
+        } else if self.locked {
+            Err(JoinError::Locked)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn can_spectate(&self, user_id: UserId) -> Result<(), JoinError> {
+        if self.is_member(user_id) {
+            Err(JoinError::AlreadyJoined)
+        } else if self.spectators.len() >= MAX_SPECTATORS {
+            Err(JoinError::Full)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_member(&self, user_id: UserId) -> bool {
+        self.players.contains(&user_id) || self.spectators.contains(&user_id)
+    }
+
+    // everybody who gets the round's updates
+    fn members(&self) -> Vec<UserId> {
+        self.players
+            .iter()
+            .chain(self.spectators.iter())
+            .copied()
+            .collect()
+    }
+}
+
+pub type ItemId = usize;
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Item {
     id: ItemId,
     label: String,
     state: u8,
     user_id: i32,
+    control_type: ControlType,
     max_value: u8,
+    instruction: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -93,36 +581,124 @@ pub enum ClientState {
     Lobby {
         player_count: usize,
         player_ready_count: usize,
+        players: Vec<LobbyPlayer>,
+        host: bool,
+        invite_code: Option<String>,
+        difficulty: Difficulty,
+        capacity: usize,
+        tick_interval: i32,
+        locked: bool,
     },
     InGame {
         current_instruction: String,
         ui_items: Vec<ClientUiItem>,
         instructions_executed: usize,
         instructions_missed: usize,
+        level: usize,
+        level_count: usize,
+        level_progress: usize,
+        level_target: usize,
     },
+    Finished {
+        levels_completed: usize,
+        instructions_executed: usize,
+        instructions_missed: usize,
+    },
+    // level is 0 while the round is in the lobby
+    Spectating {
+        players: Vec<SpectatedPlayer>,
+        instructions_executed: usize,
+        instructions_missed: usize,
+        level: usize,
+        level_count: usize,
+        level_progress: usize,
+        level_target: usize,
+        finished: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpectatedPlayer {
+    user_id: UserId,
+    username: String,
+    current_instruction: Option<String>,
+    ui_items: Vec<ClientUiItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LobbyPlayer {
+    user_id: UserId,
+    username: String,
+    ready: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ClientUiItem {
-    id: ItemId,
-    label: String,
-    state: u8,
-    max_value: u8,
+    pub id: ItemId,
+    pub label: String,
+    pub state: u8,
+    pub control_type: ControlType,
+    pub max_value: u8,
 }
 
 fn client_state_for_user(user_id: UserId, round: &RocketJamRound) -> Option<ClientState> {
+    if round.spectators.contains(&user_id) {
+        return Some(spectating(round));
+    }
     match &round.game {
         RocketJam::InLobby { players_ready } => Some(ClientState::Lobby {
             player_count: round.players.len(),
             player_ready_count: players_ready.len(),
+            players: round
+                .players
+                .iter()
+                .map(|player| LobbyPlayer {
+                    user_id: *player,
+                    username: round.player_names.get(player).cloned().unwrap_or_default(),
+                    ready: players_ready.contains(player),
+                })
+                .collect(),
+            host: round.host == user_id,
+            invite_code: round.invite_code.clone(),
+            difficulty: round.difficulty,
+            capacity: round.capacity,
+            tick_interval: round.tick_interval,
+            locked: round.locked,
         }),
 
-        RocketJam::InLevel(round_state) => level_for_user(user_id, &round_state),
+        RocketJam::InLevel(round_state) => level_for_user(user_id, round_state),
+        RocketJam::Finished(round_state) => Some(ClientState::Finished {
+            levels_completed: round_state.level + 1,
+            instructions_executed: round_state.instructions_executed,
+            instructions_missed: round_state.instructions_missed,
+        }),
     }
 }
 
 fn level_for_user(user_id: UserId, round_state: &RoundState) -> Option<ClientState> {
-    let ui_items: Vec<ClientUiItem> = round_state
+    let ui_items = ui_items_for_user(user_id, round_state);
+    let current_instruction = match instruction_for_user(user_id, round_state) {
+        Some(instruction) => instruction,
+        None => {
+            error!("Got no instruction for user {:?}", user_id);
+            "".to_string()
+        }
+    };
+
+    Some(ClientState::InGame {
+        current_instruction,
+        ui_items,
+        instructions_executed: round_state.instructions_executed,
+        instructions_missed: round_state.instructions_missed,
+        level: round_state.level + 1,
+        level_count: LEVELS.len(),
+        level_progress: round_state.level_instructions_executed,
+        level_target: round_state.level_instructions_required,
+    })
+}
+
+fn ui_items_for_user(user_id: UserId, round_state: &RoundState) -> Vec<ClientUiItem> {
+    round_state
         .items
         .iter()
         .filter(|i| i.user_id.eq(&user_id))
@@ -130,269 +706,1511 @@ fn level_for_user(user_id: UserId, round_state: &RoundState) -> Option<ClientSta
             id: i.id,
             label: i.label.clone(),
             state: i.state,
+            control_type: i.control_type,
             max_value: i.max_value,
         })
-        .collect();
-    let current_instruction = if let Some(instruction) = round_state
+        .collect()
+}
+
+fn instruction_for_user(user_id: UserId, round_state: &RoundState) -> Option<String> {
+    let instruction = round_state
         .instructions
         .iter()
-        .find(|i| i.user_id == user_id)
-    {
-        let item = round_state
-            .items
-            .iter()
-            .find(|i| i.id == instruction.item_id)
-            .unwrap();
-        format!("Turn {} to {}", item.label, instruction.state)
-    } else {
-        error!("Got no instruction for user {:?}", user_id);
-        "".to_string()
+        .find(|i| i.user_id == user_id)?;
+    let item = round_state
+        .items
+        .iter()
+        .find(|i| i.id == instruction.item_id)?;
+    Some(instruction_text(item, instruction.state))
+}
+
+// Everybody's panel and instruction at once, spectators can't change anything
+fn spectating(round: &RocketJamRound) -> ClientState {
+    let (round_state, finished) = match &round.game {
+        RocketJam::InLobby { .. } => (None, false),
+        RocketJam::InLevel(round_state) => (Some(round_state), false),
+        RocketJam::Finished(round_state) => (Some(round_state), true),
     };
+    let players = round
+        .players
+        .iter()
+        .map(|player| SpectatedPlayer {
+            user_id: *player,
+            username: round.player_names.get(player).cloned().unwrap_or_default(),
+            current_instruction: round_state.and_then(|r| instruction_for_user(*player, r)),
+            ui_items: round_state.map_or_else(Vec::new, |r| ui_items_for_user(*player, r)),
+        })
+        .collect();
+    ClientState::Spectating {
+        players,
+        instructions_executed: round_state.map_or(0, |r| r.instructions_executed),
+        instructions_missed: round_state.map_or(0, |r| r.instructions_missed),
+        level: round_state.map_or(0, |r| r.level + 1),
+        level_count: LEVELS.len(),
+        level_progress: round_state.map_or(0, |r| r.level_instructions_executed),
+        level_target: round_state.map_or(0, |r| r.level_instructions_required),
+        finished,
+    }
+}
 
-    Some(ClientState::InGame {
-        current_instruction,
-        ui_items,
-        instructions_executed: round_state.instructions_executed,
-        instructions_missed: round_state.instructions_missed,
-    })
+fn instruction_text(item: &Item, state: u8) -> String {
+    let value = match item.control_type {
+        ControlType::Switch if state == 0 => "off".to_string(),
+        ControlType::Switch => "on".to_string(),
+        ControlType::Dial => state.to_string(),
+        // the slider sprites are labeled starting at 1
+        ControlType::Slider => (state + 1).to_string(),
+    };
+    item.instruction
+        .replace("{label}", &item.label)
+        .replace("{value}", &value)
 }
 
-pub fn init_rocket_jam(user_id: UserId) -> RocketJamRound {
+pub fn init_rocket_jam(round_id: Uuid, user_id: UserId, deck: String, seed: u64) -> RocketJamRound {
     let players_ready: Vec<UserId> = vec![];
     RocketJamRound {
-        id: Uuid::new_v4(),
+        id: round_id,
+        seed,
+        rng: Pcg32::seed_from_u64(seed),
+        deck,
         players: vec![user_id],
+        spectators: vec![],
+        capacity: DEFAULT_CAPACITY,
+        name: String::new(),
+        host: user_id,
+        player_names: HashMap::new(),
+        password_hash: None,
+        created_at_ms: 0,
+        private: false,
+        invite_code: None,
+        difficulty: Difficulty::Normal,
+        tick_interval: DEFAULT_TICK_INTERVAL,
+        locked: false,
         game: RocketJam::InLobby { players_ready },
     }
 }
 
+fn create_round(event: &RoundEvent) -> Option<RocketJamRound> {
+    match event {
+        RoundEvent::Created {
+            round_id,
+            user_id,
+            deck,
+            seed,
+            username,
+            name,
+            password_hash,
+            created_at_ms,
+            private,
+            invite_code,
+            tick_interval,
+        } => Some(RocketJamRound {
+            name: name.clone(),
+            player_names: HashMap::from([(*user_id, username.clone())]),
+            password_hash: password_hash.clone(),
+            created_at_ms: *created_at_ms,
+            private: *private,
+            invite_code: invite_code.clone(),
+            tick_interval: *tick_interval,
+            ..init_rocket_jam(*round_id, *user_id, deck.clone(), *seed)
+        }),
+        _ => None,
+    }
+}
+
 pub type RoundId = String;
 
 #[derive(Clone)]
 pub struct RocketJamApp {
     model: Arc<RwLock<Model>>,
+    catalog: Arc<ItemCatalog>,
+    chat_filter: Arc<dyn ChatFilter>,
 }
 
 pub type ClientMessage = (UserId, ToClient);
 
 impl RocketJamApp {
-    pub fn new() -> Self {
+    pub fn new(catalog: ItemCatalog, chat_filter: Box<dyn ChatFilter>) -> Self {
         RocketJamApp {
             model: Arc::new(RwLock::new(init_model())),
+            catalog: Arc::new(catalog),
+            chat_filter: Arc::from(chat_filter),
         }
     }
 
+    #[tracing::instrument(skip(self), fields(tick))]
     pub async fn tick(&self) -> Vec<ClientMessage> {
         let mut model = self.model.write().await;
-        let mut updated_rounds = HashMap::new();
+        tracing::Span::current().record("tick", &model.tick);
+        let clock = Clock {
+            tick: model.tick,
+            now_ms: now_ms(),
+        };
+        let running_rounds: Vec<RocketJamRound> = model
+            .games_by_id
+            .values()
+            .filter(|round| matches!(round.game, RocketJam::InLevel(_)))
+            .filter(|round| clock.tick % round.tick_interval.max(1) == 0)
+            .cloned()
+            .collect();
         let mut msgs = Vec::new();
-        for (key, round) in model.games_by_id.iter() {
-            let (updated_round, client_messages) = tick_round(round, model.tick);
-            updated_rounds.insert(key.clone(), updated_round);
-            let mut ccc = client_messages.clone();
-            msgs.append(&mut ccc);
+        for round in running_rounds {
+            // ticks that change nothing stay out of the log, replays don't miss them
+            let (updated_round, mut client_messages) = match tick_round(&round, clock) {
+                Some(ticked) => ticked,
+                None => continue,
+            };
+            let round_id = round.id.to_string();
+            record_event(
+                &mut model,
+                &round_id,
+                RoundEvent::Tick {
+                    tick: clock.tick,
+                    at_ms: clock.now_ms,
+                },
+            );
+            model.games_by_id.insert(round_id, updated_round);
+            msgs.append(&mut client_messages);
         }
-        model.games_by_id = updated_rounds;
         model.tick += 1;
         msgs
     }
 
     pub async fn update(&self, user: &User, msg: ToBackend) -> Vec<ClientMessage> {
-        info!("app update with msg {:?}", msg);
-        if let Some(round) = find_game_by_user_id(&user.id, &self.model).await {
-            let mut model = self.model.write().await;
-            let updated_round = update_round(user.id, &round, &msg, model.tick);
-            let round_id = round.id;
-            model
-                .games_by_id
-                .insert(round_id.to_string(), updated_round.clone());
-            round
-                .players
-                .iter()
-                .map(
-                    |user_id| match client_state_for_user(user_id.clone(), &updated_round) {
-                        Some(client_state) => {
-                            Some((*user_id, ToClient::UpdateGameState { client_state }))
-                        }
-                        None => None,
-                    },
-                )
-                .flatten()
-                .collect()
-        } else {
-            return match msg {
-                ToBackend::Init => get_available_rounds(user.id, &self.model).await,
-                ToBackend::StartGame => start_game(user.id, &self.model).await,
-                ToBackend::GetAvailableRounds => get_available_rounds(user.id, &self.model).await,
-                ToBackend::JoinGame { round_id } => {
-                    join_game(user.id, &round_id, &self.model).await
-                }
-                _ => vec![],
-            };
+        self.update_as(user, msg, false).await
+    }
+
+    // A moderator acting on their round as if they hosted it
+    pub async fn moderate(&self, user: &User, msg: ToBackend) -> Vec<ClientMessage> {
+        if user.role < Role::Moderator {
+            warn!("User {:?} is a {:?}, can't moderate", user.id, user.role);
+            return vec![];
         }
+        self.update_as(user, msg, true).await
     }
-}
 
-fn tick_round(round: &RocketJamRound, current_tick: i32) -> (RocketJamRound, Vec<ClientMessage>) {
-    match &round.game {
-        RocketJam::InLevel(round_state) => {
-            let mut instructions: Vec<Instruction> = Vec::new();
-            let mut instructions_missed = round_state.instructions_missed;
-            let mut updated = false;
-            for instruction in &round_state.instructions {
-                if instruction.eol_tick == current_tick {
-                    instructions_missed += 1;
-                    if let Some(instruction) =
-                        mk_instructions(instruction.user_id, &round_state.items, current_tick)
-                    {
-                        instructions.push(instruction);
-                        updated = true;
-                    } else {
-                        error!("Got no instruction");
+    async fn update_as(
+        &self,
+        user: &User,
+        msg: ToBackend,
+        host_override: bool,
+    ) -> Vec<ClientMessage> {
+        let lobby_changed = matches!(
+            msg,
+            ToBackend::StartGame { .. }
+                | ToBackend::JoinGame { .. }
+                | ToBackend::JoinByInviteCode { .. }
+                | ToBackend::ToggleReady
+                | ToBackend::LeaveRound
+                | ToBackend::Kick { .. }
+                | ToBackend::UpdateRoundSettings { .. }
+                | ToBackend::LockLobby { .. }
+                | ToBackend::ForceStart
+        );
+        let mut msgs = self.handle(user, msg, host_override).await;
+        if lobby_changed {
+            let model = self.model.read().await;
+            msgs.append(&mut menu_updates(user.id, &model, &self.catalog));
+        }
+        msgs
+    }
+
+    async fn handle(&self, user: &User, msg: ToBackend, host_override: bool) -> Vec<ClientMessage> {
+        info!("app update with msg {:?}", msg.name());
+        // the lock is held from reading the round until writing it back,
+        // otherwise a tick in between would be overwritten
+        let mut model = self.model.write().await;
+        // chat isn't part of the round's state and doesn't go into its events
+        match msg {
+            ToBackend::Chat { text } => {
+                return match clean_text(&text, self.chat_filter.as_ref()) {
+                    Some(text) => chat(user, ChatBody::Text(text), &mut model),
+                    None => vec![],
+                };
+            }
+            ToBackend::Emote { emote } => return chat(user, ChatBody::Emote(emote), &mut model),
+            ToBackend::Resync => return resync(user.id, &model),
+            _ => {}
+        }
+        if let Some(round) = find_game_by_user_id(&user.id, &model) {
+            let event = match msg {
+                ToBackend::RegenerateInviteCode => match mk_invite_code(&model) {
+                    Some(invite_code) => RoundEvent::InviteCodeChanged {
+                        user_id: user.id,
+                        invite_code: Some(invite_code),
+                    },
+                    None => {
+                        warn!(
+                            "No new invite code for round {:?}, keeping the old",
+                            round.id
+                        );
+                        return vec![];
                     }
-                } else {
-                    instructions.push(instruction.clone());
+                },
+                ToBackend::RevokeInviteCode => RoundEvent::InviteCodeChanged {
+                    user_id: user.id,
+                    invite_code: None,
+                },
+                msg if msg.is_round_action() => RoundEvent::Action {
+                    user_id: user.id,
+                    tick: model.tick,
+                    at_ms: now_ms(),
+                    action: msg,
+                    host_override,
+                },
+                // e.g. Init after a reload, the round's state takes the client back to it
+                msg => {
+                    info!(
+                        "User {:?} is in round {:?}, not recording {}",
+                        user.id,
+                        round.id,
+                        msg.name()
+                    );
+                    return resync(user.id, &model);
                 }
-            }
-            let updated_round_state = RoundState {
-                instructions,
-                instructions_missed,
-                ..round_state.clone()
-            };
-
-            let rocket_jam = RocketJam::InLevel(updated_round_state);
-            let updated_round = RocketJamRound {
-                game: rocket_jam,
-                ..round.clone()
             };
-            if !updated {
-                (updated_round, vec![])
-            } else {
-                let msgs: Vec<ClientMessage> = updated_round
-                    .players
-                    .iter()
-                    .map(
-                        |user_id| match client_state_for_user(*user_id, &updated_round) {
-                            Some(client_state) => {
-                                Some((*user_id, ToClient::UpdateGameState { client_state }))
-                            }
-                            None => None,
-                        },
-                    )
-                    .flatten()
-                    .collect();
-                (updated_round, msgs)
+            let updated_round = apply_event(&round, &event, &self.catalog);
+            let round_id = round.id.to_string();
+            record_event(&mut model, &round_id, event);
+            let departed: Vec<UserId> = round
+                .members()
+                .into_iter()
+                .filter(|member| !updated_round.is_member(*member))
+                .collect();
+            if !departed.is_empty() {
+                return leave_round(&departed, updated_round, &mut model, &self.catalog);
+            }
+            model.games_by_id.insert(round_id, updated_round.clone());
+            round_updates(&updated_round)
+        } else {
+            drop(model);
+            match msg {
+                ToBackend::Init => get_available_rounds(user.id, &self.model, &self.catalog).await,
+                ToBackend::StartGame {
+                    deck,
+                    seed,
+                    name,
+                    password,
+                    private,
+                } => {
+                    // a chosen seed makes the round predictable, that's for tests and operators
+                    let seed = match seed {
+                        Some(seed) if user.role >= Role::Admin => seed,
+                        Some(_) => {
+                            warn!("User {:?} may not pick the seed", user.id);
+                            thread_rng().gen()
+                        }
+                        None => thread_rng().gen(),
+                    };
+                    let settings = RoundSettings {
+                        deck,
+                        name,
+                        password,
+                        private,
+                    };
+                    start_game(user, settings, seed, &self.model, &self.catalog).await
+                }
+                ToBackend::GetAvailableRounds => {
+                    get_available_rounds(user.id, &self.model, &self.catalog).await
+                }
+                ToBackend::JoinGame { round_id, password } => {
+                    let target = JoinTarget::Round { round_id, password };
+                    join_game(user, target, &self.model, &self.catalog).await
+                }
+                ToBackend::QuickPlay { preferred_size } => {
+                    enqueue(user, preferred_size, &self.model).await
+                }
+                ToBackend::LeaveQueue => dequeue(user.id, &self.model).await,
+                ToBackend::Spectate { round_id } => {
+                    spectate(user, round_id, &self.model, &self.catalog).await
+                }
+                ToBackend::JoinByInviteCode { invite_code } => {
+                    let target = JoinTarget::InviteCode(invite_code);
+                    join_game(user, target, &self.model, &self.catalog).await
+                }
+                _ => vec![],
             }
         }
-        RocketJam::InLobby { .. } => (round.clone(), vec![]),
     }
-}
-
-async fn join_game(
-    user_id: UserId,
-    round_id: &RoundId,
-    model: &RwLock<Model>,
-) -> Vec<ClientMessage> {
-    let round = find_round_by_id(round_id, model).await;
-    match round {
-        Some(round) => {
-            let other_players = round.players.iter();
-
-            let mut players = round.players.to_vec();
-            players.push(user_id);
-            let round_with_user = RocketJamRound {
-                players,
-                ..round.clone()
-            };
 
-            let client_state = client_state_for_user(user_id, &round_with_user);
-            let mut model = model.write().await;
-            let round_id = &round_with_user.id;
-            model
-                .games_by_id
-                .insert(round_id.to_string(), round_with_user.clone());
+    // Called by the matchmaker, starts rounds for queued players who can be matched.
+    pub async fn matchmake(&self) -> Vec<ClientMessage> {
+        let mut model = self.model.write().await;
+        let mut msgs = Vec::new();
+        while let Some(user_ids) = next_match(&model.queue, now_ms()) {
+            let entries: Vec<QueueEntry> = user_ids
+                .iter()
+                .filter_map(|user_id| model.queue.iter().find(|e| e.user_id == *user_id))
+                .cloned()
+                .collect();
             model
-                .game_ids_by_user_id
-                .insert(user_id, round_id.to_string());
-            drop(model); // release lock asap
-            match client_state {
-                Some(client_state) => other_players
-                    .into_iter()
-                    .map(|user_id| {
-                        (
-                            user_id,
-                            client_state_for_user(user_id.clone(), &round_with_user),
-                        )
-                    })
-                    .map(|(user_id, client_state)| match client_state {
-                        Some(client_state) => {
-                            Some((*user_id, ToClient::UpdateGameState { client_state }))
-                        }
-                        None => None,
-                    })
-                    .flatten()
-                    .chain(vec![(user_id, ToClient::EnterRound { client_state })])
-                    .collect(),
-                None => {
-                    error!(
-                        "couldn't generate client state for user {:?} when joining game {:?}",
-                        &user_id, &round_id
-                    );
-                    vec![]
-                }
-            }
+                .queue
+                .retain(|entry| !user_ids.contains(&entry.user_id));
+            msgs.append(&mut start_match(&entries, &mut model, &self.catalog));
         }
-        None => {
-            warn!("round {:?} not found", &round_id);
-            vec![]
+        if !msgs.is_empty() {
+            msgs.append(&mut queue_positions(&model));
         }
+        msgs
     }
-}
 
-async fn find_round_by_id(round_id: &RoundId, model: &RwLock<Model>) -> Option<RocketJamRound> {
-    match model.read().await.games_by_id.get(round_id) {
-        Some(round) => Some(round.clone()),
-        None => None,
+    // the event log of a round still in memory
+    pub async fn round_log(&self, round_id: &RoundId) -> Option<Vec<RoundEvent>> {
+        let model = self.model.read().await;
+        model.round_logs.get(round_id).cloned()
+    }
+
+    pub async fn replay(
+        &self,
+        round_id: &RoundId,
+        events: &[RoundEvent],
+        query: &ReplayQuery,
+    ) -> Option<RoundReplay> {
+        let limit = query
+            .limit
+            .unwrap_or(REPLAY_PAGE_SIZE)
+            .min(MAX_REPLAY_PAGE_SIZE);
+        let page = query.offset..query.offset.saturating_add(limit);
+        let mut steps = Vec::new();
+        let replayed = replay(events, &self.catalog, |seq, event, round| {
+            if page.contains(&seq) {
+                steps.push(ReplayStep {
+                    seq,
+                    event: redacted(event),
+                    round: RocketJamRound {
+                        password_hash: round.password_hash.as_ref().map(|_| REDACTED.to_string()),
+                        invite_code: round.invite_code.as_ref().map(|_| REDACTED.to_string()),
+                        ..round.clone()
+                    },
+                });
+            }
+        })?;
+        let model = self.model.read().await;
+        let matches_live_round = model
+            .games_by_id
+            .get(round_id)
+            .map(|live| serde_json::to_value(&replayed).ok() == serde_json::to_value(live).ok());
+        Some(RoundReplay {
+            round_id: round_id.clone(),
+            event_count: events.len(),
+            offset: query.offset,
+            steps,
+            finished: matches!(replayed.game, RocketJam::Finished(_)),
+            matches_live_round,
+        })
+    }
+
+    pub async fn round_overviews(&self) -> Vec<RoundOverview> {
+        let model = self.model.read().await;
+        let mut overviews: Vec<RoundOverview> = model
+            .games_by_id
+            .values()
+            .map(|round| RoundOverview {
+                summary: round.summary(),
+                players: round.players.clone(),
+                spectators: round.spectators.clone(),
+                private: round.private,
+                game: round.game.clone(),
+            })
+            .collect();
+        overviews.sort_by_key(|overview| overview.summary.created_at_ms);
+        overviews
+    }
+
+    // None if there's no such round
+    pub async fn end_round(&self, round_id: &RoundId) -> Option<Vec<ClientMessage>> {
+        let mut model = self.model.write().await;
+        let round = model.games_by_id.get(round_id)?.clone();
+        info!("Ending round {:?}", round_id);
+        let event = RoundEvent::Ended { at_ms: now_ms() };
+        let ended_round = apply_event(&round, &event, &self.catalog);
+        record_event(&mut model, round_id, event);
+        model
+            .games_by_id
+            .insert(round_id.clone(), ended_round.clone());
+        let mut msgs = round_updates(&ended_round);
+        msgs.append(&mut menu_updates_except(&[], &model, &self.catalog));
+        Some(msgs)
+    }
+
+    // Sends everybody in the round back to the menu, None if there's no such round
+    pub async fn delete_round(&self, round_id: &RoundId) -> Option<Vec<ClientMessage>> {
+        let mut model = self.model.write().await;
+        let round = model.games_by_id.get(round_id)?.clone();
+        info!("Deleting round {:?}", round_id);
+        let members = round.members();
+        let empty_round = RocketJamRound {
+            players: vec![],
+            spectators: vec![],
+            ..round
+        };
+        let mut msgs = leave_round(&members, empty_round, &mut model, &self.catalog);
+        // unlike everybody leaving, this takes the round's history along
+        model
+            .closed_rounds
+            .retain(|snapshot| snapshot.round_id != *round_id);
+        model.deleted_round_ids.insert(round_id.clone());
+        msgs.append(&mut menu_updates_except(&members, &model, &self.catalog));
+        Some(msgs)
+    }
+
+    // Takes the user out of their round, the queue and the menu
+    pub async fn kick(&self, user: &User) -> Vec<ClientMessage> {
+        let msgs = self.update(user, ToBackend::LeaveRound).await;
+        let mut model = self.model.write().await;
+        model.menu_user_ids.remove(&user.id);
+        let queue_length = model.queue.len();
+        model.queue.retain(|entry| entry.user_id != user.id);
+        let mut msgs: Vec<ClientMessage> = msgs
+            .into_iter()
+            .filter(|(user_id, _)| *user_id != user.id)
+            .collect();
+        if model.queue.len() < queue_length {
+            msgs.append(&mut queue_positions(&model));
+        }
+        msgs
+    }
+
+    // Users whose event stream closed stop getting rounds list updates and
+    // leave the queue, they'd be matched into rounds they never get ready in
+    pub async fn prune_disconnected(
+        &self,
+        connected_user_ids: &HashSet<UserId>,
+    ) -> Vec<ClientMessage> {
+        let mut model = self.model.write().await;
+        model
+            .menu_user_ids
+            .retain(|user_id| connected_user_ids.contains(user_id));
+        let queue_length = model.queue.len();
+        model
+            .queue
+            .retain(|entry| connected_user_ids.contains(&entry.user_id));
+        if model.queue.len() < queue_length {
+            queue_positions(&model)
+        } else {
+            vec![]
+        }
+    }
+
+    pub async fn round_id_for_user(&self, user_id: UserId) -> Option<RoundId> {
+        let model = self.model.read().await;
+        model.game_ids_by_user_id.get(&user_id).cloned()
+    }
+
+    pub async fn round_counts(&self) -> Vec<(&'static str, usize)> {
+        let model = self.model.read().await;
+        let mut counts = vec![("lobby", 0), ("level", 0), ("finished", 0)];
+        for round in model.games_by_id.values() {
+            let state = match round.game {
+                RocketJam::InLobby { .. } => 0,
+                RocketJam::InLevel(_) => 1,
+                RocketJam::Finished(_) => 2,
+            };
+            counts[state].1 += 1;
+        }
+        counts
+    }
+
+    pub async fn take_deleted_rounds(&self) -> Vec<RoundId> {
+        let mut model = self.model.write().await;
+        model.deleted_round_ids.drain().collect()
+    }
+
+    pub async fn take_dirty_rounds(&self) -> Vec<RoundSnapshot> {
+        let mut model = self.model.write().await;
+        let dirty_round_ids: Vec<RoundId> = model.dirty_round_ids.drain().collect();
+        let mut snapshots: Vec<RoundSnapshot> = model.closed_rounds.drain(..).collect();
+        for round_id in dirty_round_ids {
+            let (round, events) = match (
+                model.games_by_id.get(&round_id),
+                model.round_logs.get(&round_id),
+            ) {
+                (Some(round), Some(events)) => (round, events),
+                _ => continue,
+            };
+            let persisted = model
+                .persisted_event_counts
+                .get(&round_id)
+                .copied()
+                .unwrap_or(0);
+            snapshots.push(snapshot(round, events, persisted));
+            let event_count = events.len();
+            model.persisted_event_counts.insert(round_id, event_count);
+        }
+        snapshots
+    }
+
+    // Rebuilds rounds from their persisted event logs after a restart.
+    pub async fn restore(&self, round_logs: Vec<Vec<RoundEvent>>) {
+        let mut model = self.model.write().await;
+        for events in round_logs {
+            let round = match replay(&events, &self.catalog, |_, _, _| {}) {
+                Some(round) => round,
+                None => continue,
+            };
+            let round_id = round.id.to_string();
+            info!(
+                "Restoring round {:?} from {:?} events",
+                round_id,
+                events.len()
+            );
+            // ticks continue after the last one the round has seen
+            let last_tick = events
+                .iter()
+                .filter_map(|event| match event {
+                    RoundEvent::Action { tick, .. } | RoundEvent::Tick { tick, .. } => Some(*tick),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+            model.tick = model.tick.max(last_tick + 1);
+            for user_id in round.members() {
+                model.game_ids_by_user_id.insert(user_id, round_id.clone());
+            }
+            model
+                .persisted_event_counts
+                .insert(round_id.clone(), events.len());
+            model.round_logs.insert(round_id.clone(), events);
+            model.games_by_id.insert(round_id, round);
+        }
     }
 }
 
-async fn get_available_rounds(user_id: UserId, model: &RwLock<Model>) -> Vec<ClientMessage> {
-    info!("get_availble_rounds for {:?}", user_id);
-    let model = model.read().await;
-    let round_ids: Vec<String> = model
-        .games_by_id
-        .values()
-        .filter(|round| {
-            if let RocketJam::InLobby { .. } = round.game {
-                true
+// A round as operators see it in the admin API
+#[derive(Serialize, Clone, Debug)]
+pub struct RoundOverview {
+    summary: RoundSummary,
+    players: Vec<UserId>,
+    spectators: Vec<UserId>,
+    private: bool,
+    game: RocketJam,
+}
+
+pub struct RoundSnapshot {
+    pub round_id: RoundId,
+    pub deck: String,
+    pub seed: u64,
+    pub players: Vec<UserId>,
+    pub level: usize,
+    pub instructions_executed: usize,
+    pub instructions_missed: usize,
+    pub started: bool,
+    pub outcome: Option<RoundOutcome>,
+    pub player_stats: HashMap<UserId, PlayerStats>,
+    // the events since the last snapshot, the first of them at `first_seq` in the log
+    pub first_seq: usize,
+    pub new_events: Vec<RoundEvent>,
+}
+
+fn snapshot(round: &RocketJamRound, events: &[RoundEvent], first_seq: usize) -> RoundSnapshot {
+    let (round_state, outcome) = match &round.game {
+        RocketJam::InLobby { .. } => (None, None),
+        RocketJam::InLevel(round_state) => (Some(round_state), None),
+        RocketJam::Finished(round_state) if round_state.completed => {
+            (Some(round_state), Some(RoundOutcome::Completed))
+        }
+        RocketJam::Finished(round_state) => (Some(round_state), Some(RoundOutcome::Ended)),
+    };
+    // whoever left after playing keeps their stats
+    let mut players = round.players.clone();
+    if let Some(round_state) = round_state {
+        for user_id in round_state.player_stats.keys() {
+            if !players.contains(user_id) {
+                players.push(*user_id);
+            }
+        }
+    }
+    RoundSnapshot {
+        round_id: round.id.to_string(),
+        deck: round.deck.clone(),
+        seed: round.seed,
+        players,
+        level: round_state.map_or(0, |r| r.level),
+        instructions_executed: round_state.map_or(0, |r| r.instructions_executed),
+        instructions_missed: round_state.map_or(0, |r| r.instructions_missed),
+        // a lobby ended early is finished without ever having had a level
+        started: round_state.is_some_and(|r| !r.items.is_empty()),
+        outcome,
+        player_stats: round_state.map_or_else(HashMap::new, |r| r.player_stats.clone()),
+        first_seq,
+        new_events: events[first_seq.min(events.len())..].to_vec(),
+    }
+}
+
+fn record_event(model: &mut Model, round_id: &RoundId, event: RoundEvent) {
+    model
+        .round_logs
+        .entry(round_id.clone())
+        .or_default()
+        .push(event);
+    model.dirty_round_ids.insert(round_id.clone());
+}
+
+fn apply_event(
+    round: &RocketJamRound,
+    event: &RoundEvent,
+    catalog: &ItemCatalog,
+) -> RocketJamRound {
+    match event {
+        RoundEvent::Created { .. } => {
+            warn!("Round {:?} was already created", round.id);
+            round.clone()
+        }
+        RoundEvent::InviteCodeChanged {
+            user_id,
+            invite_code,
+        } => {
+            if *user_id != round.host {
+                warn!("Only the host can change the invite code of {:?}", round.id);
+                return round.clone();
+            }
+            RocketJamRound {
+                invite_code: invite_code.clone(),
+                ..round.clone()
+            }
+        }
+        RoundEvent::Joined { user_id, username } => {
+            if let Err(e) = round.can_join(*user_id) {
+                warn!(
+                    "User {:?} can't join round {:?}: {:?}",
+                    user_id, round.id, e
+                );
+                return round.clone();
+            }
+            let mut players = round.players.to_vec();
+            players.push(*user_id);
+            let mut player_names = round.player_names.clone();
+            player_names.insert(*user_id, username.clone());
+            RocketJamRound {
+                players,
+                player_names,
+                ..round.clone()
+            }
+        }
+        RoundEvent::SpectatorJoined { user_id, username } => {
+            if let Err(e) = round.can_spectate(*user_id) {
+                warn!(
+                    "User {:?} can't spectate round {:?}: {:?}",
+                    user_id, round.id, e
+                );
+                return round.clone();
+            }
+            let mut spectators = round.spectators.to_vec();
+            spectators.push(*user_id);
+            let mut player_names = round.player_names.clone();
+            player_names.insert(*user_id, username.clone());
+            RocketJamRound {
+                spectators,
+                player_names,
+                ..round.clone()
+            }
+        }
+        RoundEvent::Action {
+            user_id,
+            tick,
+            at_ms,
+            action,
+            host_override,
+        } => {
+            let clock = Clock {
+                tick: *tick,
+                now_ms: *at_ms,
+            };
+            update_round(*user_id, *host_override, round, action, clock, catalog)
+        }
+        RoundEvent::Tick { tick, at_ms } => {
+            let clock = Clock {
+                tick: *tick,
+                now_ms: *at_ms,
+            };
+            tick_round(round, clock).map_or_else(|| round.clone(), |(round, _)| round)
+        }
+        RoundEvent::Ended { .. } => {
+            let round_state = match &round.game {
+                RocketJam::InLevel(round_state) => RoundState {
+                    instructions: vec![],
+                    ..round_state.clone()
+                },
+                RocketJam::InLobby { .. } => RoundState {
+                    level: 0,
+                    items: vec![],
+                    instructions: vec![],
+                    level_instructions_executed: 0,
+                    level_instructions_required: 0,
+                    instructions_executed: 0,
+                    instructions_missed: 0,
+                    player_stats: HashMap::new(),
+                    completed: false,
+                },
+                RocketJam::Finished(_) => return round.clone(),
+            };
+            RocketJamRound {
+                game: RocketJam::Finished(round_state),
+                ..round.clone()
+            }
+        }
+    }
+}
+
+const REDACTED: &str = "********";
+
+// Replays go to all the players, round passwords and invite codes were
+// only for some. Short passwords are easily found from their hash.
+fn redacted(event: &RoundEvent) -> RoundEvent {
+    let mut event = event.clone();
+    if let RoundEvent::Created {
+        password_hash: Some(password_hash),
+        ..
+    } = &mut event
+    {
+        *password_hash = REDACTED.to_string();
+    }
+    if let RoundEvent::Created {
+        invite_code: Some(invite_code),
+        ..
+    }
+    | RoundEvent::InviteCodeChanged {
+        invite_code: Some(invite_code),
+        ..
+    } = &mut event
+    {
+        *invite_code = REDACTED.to_string();
+    }
+    event
+}
+
+// Rebuilds a round from its event log, `on_step` sees its state after each event.
+fn replay(
+    events: &[RoundEvent],
+    catalog: &ItemCatalog,
+    mut on_step: impl FnMut(usize, &RoundEvent, &RocketJamRound),
+) -> Option<RocketJamRound> {
+    let mut state: Option<RocketJamRound> = None;
+    for (seq, event) in events.iter().enumerate() {
+        let next = match &state {
+            Some(round) => apply_event(round, event, catalog),
+            None => match create_round(event) {
+                Some(round) => round,
+                None => {
+                    warn!("Event log doesn't start with the round's creation");
+                    break;
+                }
+            },
+        };
+        on_step(seq, event, &next);
+        state = Some(next);
+    }
+    state
+}
+
+// Whoever played in the round at some point, spectators aside
+pub fn participants(events: &[RoundEvent]) -> Vec<UserId> {
+    let mut user_ids: Vec<UserId> = Vec::new();
+    for event in events {
+        if let RoundEvent::Created { user_id, .. } | RoundEvent::Joined { user_id, .. } = event {
+            if !user_ids.contains(user_id) {
+                user_ids.push(*user_id);
+            }
+        }
+    }
+    user_ids
+}
+
+// None if no instruction ran out, the round stays as it is
+fn tick_round(
+    round: &RocketJamRound,
+    clock: Clock,
+) -> Option<(RocketJamRound, Vec<ClientMessage>)> {
+    match &round.game {
+        RocketJam::InLevel(round_state) => {
+            let mut rng = round.rng.clone();
+            let mut instructions: Vec<Instruction> = Vec::new();
+            let mut instructions_missed = round_state.instructions_missed;
+            let mut updated = false;
+            for instruction in &round_state.instructions {
+                if instruction.eol_tick <= clock.tick {
+                    instructions_missed += 1;
+                    updated = true;
+                    if let Some(instruction) = mk_instructions(
+                        instruction.user_id,
+                        &round_state.items,
+                        clock,
+                        instruction_ttl(round, round_state.level),
+                        &mut rng,
+                    ) {
+                        instructions.push(instruction);
+                    } else {
+                        error!("Got no instruction");
+                    }
+                } else {
+                    instructions.push(instruction.clone());
+                }
+            }
+            let updated_round_state = RoundState {
+                instructions,
+                instructions_missed,
+                ..round_state.clone()
+            };
+
+            let rocket_jam = RocketJam::InLevel(updated_round_state);
+            let updated_round = RocketJamRound {
+                game: rocket_jam,
+                rng,
+                ..round.clone()
+            };
+            if !updated {
+                None
             } else {
-                false
+                let msgs = round_updates(&updated_round);
+                Some((updated_round, msgs))
+            }
+        }
+        RocketJam::InLobby { .. } | RocketJam::Finished(_) => None,
+    }
+}
+
+async fn join_game(
+    user: &User,
+    target: JoinTarget,
+    model: &RwLock<Model>,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    let user_id = user.id;
+    let mut model = model.write().await;
+    let (round, password_matches) = match target {
+        JoinTarget::Round { round_id, password } => {
+            let round = model
+                .games_by_id
+                .get(&round_id)
+                .filter(|round| !round.private)
+                .cloned();
+            let password_matches = round
+                .as_ref()
+                .is_none_or(|round| round.password_matches(password.as_deref()));
+            (round, password_matches)
+        }
+        // the code stands in for the password
+        JoinTarget::InviteCode(invite_code) => {
+            let tried_at_ms = model.invite_code_tried_at_ms.entry(user_id).or_default();
+            if !within_rate_limit(
+                tried_at_ms,
+                now_ms(),
+                INVITE_CODE_TRY_LIMIT,
+                INVITE_CODE_TRY_WINDOW_MS,
+            ) {
+                warn!("User {:?} tries invite codes too fast", user_id);
+                return join_failed(user_id, "Too many invite codes tried, wait a minute");
             }
+            (
+                find_round_by_invite_code(&invite_code, &model).cloned(),
+                true,
+            )
+        }
+    };
+    match round {
+        Some(round) if model.game_ids_by_user_id.contains_key(&user_id) => {
+            warn!(
+                "User {:?} is already in a round, can't join {:?}",
+                user_id, round.id
+            );
+            vec![]
+        }
+        Some(round) if round.can_join(user_id).is_err() => {
+            warn!(
+                "User {:?} can't join round {:?}: {:?}",
+                user_id,
+                round.id,
+                round.can_join(user_id)
+            );
+            // the client's list is outdated
+            vec![available_rounds(user_id, &model, catalog)]
+        }
+        Some(round) if !password_matches => {
+            warn!("Wrong password for round {:?}", round.id);
+            join_failed(user_id, "Wrong password")
+        }
+        Some(round) => add_player(&round, user_id, &user.username, &mut model, catalog),
+        None => {
+            warn!("round for user {:?} to join not found", user_id);
+            join_failed(user_id, "There's no such round")
+        }
+    }
+}
+
+// Salted with the round, the same password hashes differently in each
+fn hash_password(round_id: &Uuid, password: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(format!("{}:{}", round_id, password).as_bytes())
+    )
+}
+
+fn join_failed(user_id: UserId, message: &str) -> Vec<ClientMessage> {
+    vec![(
+        user_id,
+        ToClient::JoinFailed {
+            message: message.to_string(),
+        },
+    )]
+}
+
+async fn spectate(
+    user: &User,
+    round_id: RoundId,
+    model: &RwLock<Model>,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    let user_id = user.id;
+    let mut model = model.write().await;
+    let round = match model
+        .games_by_id
+        .get(&round_id)
+        .filter(|round| !round.private)
+    {
+        Some(round) => round.clone(),
+        None => {
+            warn!("round for user {:?} to spectate not found", user_id);
+            return vec![];
+        }
+    };
+    if model.game_ids_by_user_id.contains_key(&user_id) {
+        warn!(
+            "User {:?} is already in a round, can't spectate {:?}",
+            user_id, round.id
+        );
+        return vec![];
+    }
+    if let Err(e) = round.can_spectate(user_id) {
+        warn!(
+            "User {:?} can't spectate round {:?}: {:?}",
+            user_id, round.id, e
+        );
+        return vec![];
+    }
+    let event = RoundEvent::SpectatorJoined {
+        user_id,
+        username: user.username.clone(),
+    };
+    let round_with_spectator = apply_event(&round, &event, catalog);
+    record_event(&mut model, &round_id, event);
+    model
+        .games_by_id
+        .insert(round_id.clone(), round_with_spectator.clone());
+    model.game_ids_by_user_id.insert(user_id, round_id.clone());
+    model.menu_user_ids.remove(&user_id);
+    model.queue.retain(|entry| entry.user_id != user_id);
+    info!("User {:?} spectates round {:?}", user_id, round.id);
+    let mut msgs = vec![(
+        user_id,
+        ToClient::EnterRound {
+            client_state: spectating(&round_with_spectator),
+        },
+    )];
+    msgs.append(&mut chat_history(
+        user_id,
+        &ChatChannel::Round(round_id),
+        &model,
+    ));
+    msgs
+}
+
+fn add_player(
+    round: &RocketJamRound,
+    user_id: UserId,
+    username: &str,
+    model: &mut Model,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    let event = RoundEvent::Joined {
+        user_id,
+        username: username.to_string(),
+    };
+    let round_with_user = apply_event(round, &event, catalog);
+    let round_id = round.id.to_string();
+    record_event(model, &round_id, event);
+    model
+        .games_by_id
+        .insert(round_id.clone(), round_with_user.clone());
+    model.game_ids_by_user_id.insert(user_id, round_id.clone());
+    model.menu_user_ids.remove(&user_id);
+    model.queue.retain(|entry| entry.user_id != user_id);
+    match client_state_for_user(user_id, &round_with_user) {
+        Some(client_state) => round
+            .members()
+            .into_iter()
+            .filter_map(|other_member| {
+                client_state_for_user(other_member, &round_with_user)
+                    .map(|client_state| (other_member, ToClient::UpdateGameState { client_state }))
+            })
+            .chain(vec![(user_id, ToClient::EnterRound { client_state })])
+            .chain(chat_history(user_id, &ChatChannel::Round(round_id), model))
+            .collect(),
+        None => {
+            error!(
+                "couldn't generate client state for user {:?} when joining game {:?}",
+                &user_id, &round_id
+            );
+            vec![]
+        }
+    }
+}
+
+// `round` is the round without the users who left or got kicked
+fn leave_round(
+    departed: &[UserId],
+    round: RocketJamRound,
+    model: &mut Model,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    let round_id = round.id.to_string();
+    // spectators can't keep a round without players alive
+    let departed: Vec<UserId> = if round.players.is_empty() {
+        departed
+            .iter()
+            .chain(round.spectators.iter())
+            .copied()
+            .collect()
+    } else {
+        departed.to_vec()
+    };
+    for user_id in &departed {
+        info!("User {:?} left round {:?}", user_id, round_id);
+        model.game_ids_by_user_id.remove(user_id);
+        model.menu_user_ids.insert(*user_id);
+    }
+    if round.players.is_empty() {
+        model.games_by_id.remove(&round_id);
+        let events = model.round_logs.remove(&round_id).unwrap_or_default();
+        let persisted = model.persisted_event_counts.remove(&round_id).unwrap_or(0);
+        model.dirty_round_ids.remove(&round_id);
+        model.chats.remove(&ChatChannel::Round(round_id.clone()));
+        if matches!(round.game, RocketJam::InLobby { .. }) {
+            info!(
+                "Round {:?} is empty and never started, deleting it",
+                round_id
+            );
+            model.deleted_round_ids.insert(round_id);
+        } else {
+            // its history and stats stay
+            info!("Round {:?} is empty, closing it", round_id);
+            let mut snapshot = snapshot(&round, &events, persisted);
+            snapshot.outcome.get_or_insert(RoundOutcome::Abandoned);
+            model.closed_rounds.push(snapshot);
+        }
+    } else {
+        model.games_by_id.insert(round_id, round.clone());
+    }
+    let mut msgs = if round.players.is_empty() {
+        vec![]
+    } else {
+        round_updates(&round)
+    };
+    for user_id in &departed {
+        msgs.push(available_rounds(*user_id, model, catalog));
+        msgs.append(&mut chat_history(*user_id, &ChatChannel::Lobby, model));
+    }
+    msgs
+}
+
+// The user's state in full, whatever they were sent before
+fn resync(user_id: UserId, model: &Model) -> Vec<ClientMessage> {
+    find_game_by_user_id(&user_id, model)
+        .and_then(|round| client_state_for_user(user_id, &round))
+        .map(|client_state| vec![(user_id, ToClient::UpdateGameState { client_state })])
+        .unwrap_or_default()
+}
+
+// The round's state for everybody in it
+fn round_updates(round: &RocketJamRound) -> Vec<ClientMessage> {
+    round
+        .members()
+        .into_iter()
+        .filter_map(|user_id| {
+            client_state_for_user(user_id, round)
+                .map(|client_state| (user_id, ToClient::UpdateGameState { client_state }))
+        })
+        .collect()
+}
+
+async fn get_available_rounds(
+    user_id: UserId,
+    model: &RwLock<Model>,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    info!("get_availble_rounds for {:?}", user_id);
+    let mut model = model.write().await;
+    let entered_menu = model.menu_user_ids.insert(user_id);
+    let mut msgs = vec![available_rounds(user_id, &model, catalog)];
+    if entered_menu {
+        msgs.append(&mut chat_history(user_id, &ChatChannel::Lobby, &model));
+    }
+    msgs
+}
+
+fn chat(user: &User, body: ChatBody, model: &mut Model) -> Vec<ClientMessage> {
+    let (channel, recipients) = match find_game_by_user_id(&user.id, model) {
+        Some(round) => (ChatChannel::Round(round.id.to_string()), round.members()),
+        None if model.menu_user_ids.contains(&user.id) => (
+            ChatChannel::Lobby,
+            model.menu_user_ids.iter().copied().collect(),
+        ),
+        None => {
+            warn!("User {:?} chats outside of rounds and the lobby", user.id);
+            return vec![];
+        }
+    };
+    let now_ms = now_ms();
+    let sent_at_ms = model.chat_sent_at_ms.entry(user.id).or_default();
+    if !within_rate_limit(sent_at_ms, now_ms, CHAT_RATE_LIMIT, CHAT_RATE_WINDOW_MS) {
+        warn!("User {:?} chats too fast, dropping {:?}", user.id, body);
+        return vec![];
+    }
+    let message = ChatEntry {
+        user_id: user.id,
+        username: user.username.clone(),
+        body,
+        sent_at_ms: now_ms,
+    };
+    let history = model.chats.entry(channel).or_default();
+    history.push(message.clone());
+    if history.len() > CHAT_HISTORY_LENGTH {
+        history.remove(0);
+    }
+    recipients
+        .into_iter()
+        .map(|recipient| {
+            let message = message.clone();
+            (recipient, ToClient::ChatMessage { message })
         })
-        .map(|round| round.id.to_string())
+        .collect()
+}
+
+fn chat_history(user_id: UserId, channel: &ChatChannel, model: &Model) -> Vec<ClientMessage> {
+    model.chats.get(channel).map_or_else(Vec::new, |history| {
+        history
+            .iter()
+            .map(|message| {
+                let message = message.clone();
+                (user_id, ToClient::ChatMessage { message })
+            })
+            .collect()
+    })
+}
+
+fn available_rounds(user_id: UserId, model: &Model, catalog: &ItemCatalog) -> ClientMessage {
+    let mut rounds: Vec<&RocketJamRound> = model
+        .games_by_id
+        .values()
+        .filter(|round| !round.private && round.can_join(user_id).is_ok())
+        .collect();
+    rounds.sort_by_key(|round| round.created_at_ms);
+    let rounds = rounds.iter().map(|round| round.summary()).collect();
+    let mut running_rounds: Vec<&RocketJamRound> = model
+        .games_by_id
+        .values()
+        .filter(|round| !round.private && matches!(round.game, RocketJam::InLevel(_)))
         .collect();
-    vec![(user_id, ToClient::AvailableRounds { round_ids })]
+    running_rounds.sort_by_key(|round| round.created_at_ms);
+    let running_rounds = running_rounds.iter().map(|round| round.summary()).collect();
+    let decks = catalog.deck_names();
+    (
+        user_id,
+        ToClient::AvailableRounds {
+            rounds,
+            running_rounds,
+            decks,
+        },
+    )
+}
+
+// The rounds list for everybody on the menu, except `user_id` who got theirs already
+fn menu_updates(user_id: UserId, model: &Model, catalog: &ItemCatalog) -> Vec<ClientMessage> {
+    menu_updates_except(&[user_id], model, catalog)
+}
+
+fn menu_updates_except(
+    user_ids: &[UserId],
+    model: &Model,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    model
+        .menu_user_ids
+        .iter()
+        .filter(|menu_user_id| !user_ids.contains(menu_user_id))
+        .map(|menu_user_id| available_rounds(*menu_user_id, model, catalog))
+        .collect()
+}
+
+fn find_round_by_invite_code<'a>(
+    invite_code: &str,
+    model: &'a Model,
+) -> Option<&'a RocketJamRound> {
+    let invite_code = invite_code.trim().to_uppercase();
+    model
+        .games_by_id
+        .values()
+        .find(|round| round.invite_code.as_deref() == Some(invite_code.as_str()))
+}
+
+// e.g. ROCKET-4271KX, about 57 million codes
+fn mk_invite_code(model: &Model) -> Option<String> {
+    let mut rng = thread_rng();
+    (0..MAX_INVITE_CODE_ATTEMPTS)
+        .map(|_| {
+            format!(
+                "{}-{:04}{}{}",
+                INVITE_CODE_WORDS.choose(&mut rng).unwrap(),
+                rng.gen_range(0, 10_000),
+                *INVITE_CODE_LETTERS.choose(&mut rng).unwrap() as char,
+                *INVITE_CODE_LETTERS.choose(&mut rng).unwrap() as char
+            )
+        })
+        .find(|invite_code| find_round_by_invite_code(invite_code, model).is_none())
+}
+
+struct RoundSettings {
+    deck: Option<String>,
+    name: Option<String>,
+    password: Option<String>,
+    private: bool,
 }
 
-async fn start_game(user_id: UserId, model: &RwLock<Model>) -> Vec<ClientMessage> {
-    info!("Starting new game");
-    let new_round = init_rocket_jam(user_id);
+async fn start_game(
+    user: &User,
+    settings: RoundSettings,
+    seed: u64,
+    model: &RwLock<Model>,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
     let mut model = model.write().await;
+    let new_round = match open_round(user.id, &user.username, settings, seed, &mut model, catalog) {
+        Some(new_round) => new_round,
+        None => return vec![],
+    };
+    match client_state_for_user(user.id, &new_round) {
+        Some(client_state) => vec![(user.id, ToClient::EnterRound { client_state })],
+        None => vec![],
+    }
+}
+
+fn open_round(
+    user_id: UserId,
+    username: &str,
+    settings: RoundSettings,
+    seed: u64,
+    model: &mut Model,
+    catalog: &ItemCatalog,
+) -> Option<RocketJamRound> {
+    // e.g. matched into a round since the request came in
+    if model.game_ids_by_user_id.contains_key(&user_id) {
+        warn!("User {:?} is already in a round, can't open one", user_id);
+        return None;
+    }
+    let RoundSettings {
+        deck,
+        name,
+        password,
+        private,
+    } = settings;
+    let deck = match deck {
+        Some(deck) if catalog.deck(&deck).is_some() => deck,
+        Some(deck) => {
+            warn!("Unknown deck {:?}, using default", deck);
+            catalog.default_deck.clone()
+        }
+        None => catalog.default_deck.clone(),
+    };
+    let name = match name.as_deref().map(str::trim) {
+        Some(name) if !name.is_empty() => name.chars().take(MAX_ROUND_NAME_LENGTH).collect(),
+        _ => format!("{}'s round", username),
+    };
+    info!("Starting new game with deck {:?} and seed {:?}", deck, seed);
+    let round_id = Uuid::new_v4();
+    let password_hash = password
+        .filter(|password| !password.is_empty())
+        .map(|password| hash_password(&round_id, &password));
+    // without a code the host can still ask for a new one in the lobby
+    let invite_code = if private { mk_invite_code(model) } else { None };
+    let event = RoundEvent::Created {
+        round_id,
+        user_id,
+        deck: deck.clone(),
+        seed,
+        username: username.to_string(),
+        name,
+        password_hash,
+        created_at_ms: now_ms(),
+        private,
+        invite_code,
+        tick_interval: DEFAULT_TICK_INTERVAL,
+    };
+    let new_round =
+        create_round(&event).unwrap_or_else(|| init_rocket_jam(round_id, user_id, deck, seed));
+    record_event(model, &round_id.to_string(), event);
+    model.menu_user_ids.remove(&user_id);
+    model.queue.retain(|entry| entry.user_id != user_id);
     model
         .game_ids_by_user_id
         .insert(user_id, new_round.id.to_string());
     model
         .games_by_id
         .insert(new_round.id.to_string(), new_round.clone());
-    if let Some(client_state) = client_state_for_user(user_id, &new_round) {
-        return vec![(user_id, ToClient::EnterRound { client_state })];
+    Some(new_round)
+}
+
+async fn enqueue(
+    user: &User,
+    preferred_size: Option<usize>,
+    model: &RwLock<Model>,
+) -> Vec<ClientMessage> {
+    let preferred_size = preferred_size
+        .unwrap_or(MIN_MATCH_SIZE)
+        .clamp(MIN_MATCH_SIZE, DEFAULT_CAPACITY);
+    let mut model = model.write().await;
+    match model
+        .queue
+        .iter_mut()
+        .find(|entry| entry.user_id == user.id)
+    {
+        // queueing again only changes the preference, the place in the queue is kept
+        Some(entry) => entry.preferred_size = preferred_size,
+        None => model.queue.push(QueueEntry {
+            user_id: user.id,
+            username: user.username.clone(),
+            preferred_size,
+            queued_at_ms: now_ms(),
+        }),
+    }
+    info!(
+        "User {:?} queued for a round of {:?}",
+        user.id, preferred_size
+    );
+    queue_positions(&model)
+}
+
+async fn dequeue(user_id: UserId, model: &RwLock<Model>) -> Vec<ClientMessage> {
+    let mut model = model.write().await;
+    model.queue.retain(|entry| entry.user_id != user_id);
+    let queue_length = model.queue.len();
+    let mut msgs = vec![(
+        user_id,
+        ToClient::QueuePosition {
+            position: None,
+            queue_length,
+        },
+    )];
+    msgs.append(&mut queue_positions(&model));
+    msgs
+}
+
+fn queue_positions(model: &Model) -> Vec<ClientMessage> {
+    let queue_length = model.queue.len();
+    model
+        .queue
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let position = Some(index + 1);
+            (
+                entry.user_id,
+                ToClient::QueuePosition {
+                    position,
+                    queue_length,
+                },
+            )
+        })
+        .collect()
+}
+
+// Players are matched with others who want the same round size, longest
+// waiting first. Once someone has waited MATCH_WAIT_MS they take whoever is there.
+fn next_match(queue: &[QueueEntry], now_ms: u64) -> Option<Vec<UserId>> {
+    for entry in queue {
+        let same_size: Vec<UserId> = queue
+            .iter()
+            .filter(|other| other.preferred_size == entry.preferred_size)
+            .map(|other| other.user_id)
+            .take(entry.preferred_size)
+            .collect();
+        if same_size.len() == entry.preferred_size {
+            return Some(same_size);
+        }
+        let waited_ms = now_ms.saturating_sub(entry.queued_at_ms);
+        if waited_ms >= MATCH_WAIT_MS && queue.len() >= MIN_MATCH_SIZE {
+            let anyone: Vec<UserId> = std::iter::once(entry.user_id)
+                .chain(
+                    queue
+                        .iter()
+                        .filter(|other| other.user_id != entry.user_id)
+                        .map(|other| other.user_id),
+                )
+                .take(entry.preferred_size)
+                .collect();
+            return Some(anyone);
+        }
+    }
+    None
+}
+
+// The first player hosts the round, the others join it.
+fn start_match(
+    entries: &[QueueEntry],
+    model: &mut Model,
+    catalog: &ItemCatalog,
+) -> Vec<ClientMessage> {
+    let host = match entries.first() {
+        Some(host) => host,
+        None => return vec![],
+    };
+    let settings = RoundSettings {
+        deck: None,
+        name: Some("Quick play".to_string()),
+        password: None,
+        private: true,
+    };
+    let round = match open_round(
+        host.user_id,
+        &host.username,
+        settings,
+        thread_rng().gen(),
+        model,
+        catalog,
+    ) {
+        Some(round) => round,
+        None => return vec![],
+    };
+    let round_id = round.id.to_string();
+    info!(
+        "Matched {:?} players into round {:?}",
+        entries.len(),
+        round_id
+    );
+    let mut msgs: Vec<ClientMessage> = entries
+        .iter()
+        .map(|entry| {
+            let round_id = round_id.clone();
+            (entry.user_id, ToClient::MatchFound { round_id })
+        })
+        .collect();
+    if let Some(client_state) = client_state_for_user(host.user_id, &round) {
+        msgs.push((host.user_id, ToClient::EnterRound { client_state }));
+    }
+    for entry in entries.iter().skip(1) {
+        if let Some(round) = model.games_by_id.get(&round_id).cloned() {
+            msgs.append(&mut add_player(
+                &round,
+                entry.user_id,
+                &entry.username,
+                model,
+                catalog,
+            ));
+        }
     }
-    vec![]
+    msgs
 }
 
-async fn find_game_by_user_id(user_id: &UserId, model: &RwLock<Model>) -> Option<RocketJamRound> {
-    let model = model.read().await;
-    if let Some(game_id) = model.game_ids_by_user_id.get(&user_id) {
+fn find_game_by_user_id(user_id: &UserId, model: &Model) -> Option<RocketJamRound> {
+    if let Some(game_id) = model.game_ids_by_user_id.get(user_id) {
         if let Some(round) = model.games_by_id.get(game_id) {
             return Some(round.clone());
         }
@@ -404,46 +2222,147 @@ pub fn init_model() -> Model {
     Model {
         games_by_id: HashMap::new(),
         game_ids_by_user_id: HashMap::new(),
+        round_logs: HashMap::new(),
+        persisted_event_counts: HashMap::new(),
+        dirty_round_ids: HashSet::new(),
+        deleted_round_ids: HashSet::new(),
+        closed_rounds: Vec::new(),
+        menu_user_ids: HashSet::new(),
+        queue: Vec::new(),
+        chats: HashMap::new(),
+        chat_sent_at_ms: HashMap::new(),
+        invite_code_tried_at_ms: HashMap::new(),
         tick: 0,
     }
 }
 
 fn update_round(
     user_id: UserId,
+    host_override: bool,
     round: &RocketJamRound,
     msg: &ToBackend,
-    current_tick: i32,
+    clock: Clock,
+    catalog: &ItemCatalog,
 ) -> RocketJamRound {
+    if user_id == round.host || host_override {
+        if let Some(updated_round) = host_action(user_id, round, msg, clock, catalog) {
+            return updated_round;
+        }
+    }
+    // spectators can only leave
+    if round.spectators.contains(&user_id) {
+        return match msg {
+            ToBackend::LeaveRound => RocketJamRound {
+                spectators: round
+                    .spectators
+                    .iter()
+                    .filter(|spectator| **spectator != user_id)
+                    .copied()
+                    .collect(),
+                ..round.clone()
+            },
+            _ => round.clone(),
+        };
+    }
     match (msg, &round.game) {
+        (ToBackend::LeaveRound, _) => remove_player(user_id, round, clock, catalog),
         (ToBackend::ToggleReady, RocketJam::InLobby { players_ready }) => {
-            toggle_ready(user_id, players_ready, round, current_tick)
+            toggle_ready(user_id, players_ready, round, clock, catalog)
         }
         (ToBackend::ChangeSetting { item_id, value }, RocketJam::InLevel(game_state)) => {
-            change_setting(user_id, *item_id, *value, game_state, round, current_tick)
+            change_setting(user_id, *item_id, *value, game_state, round, clock, catalog)
         }
 
         _ => round.clone(),
     }
 }
 
+// What the host, or a moderator standing in for them, may do. None if `msg` isn't one of those.
+fn host_action(
+    user_id: UserId,
+    round: &RocketJamRound,
+    msg: &ToBackend,
+    clock: Clock,
+    catalog: &ItemCatalog,
+) -> Option<RocketJamRound> {
+    let updated_round = match (msg, &round.game) {
+        (ToBackend::Kick { user_id: kicked }, _)
+            if *kicked != user_id && round.players.contains(kicked) =>
+        {
+            info!("{:?} kicked {:?} from {:?}", user_id, kicked, round.id);
+            remove_player(*kicked, round, clock, catalog)
+        }
+        (
+            ToBackend::UpdateRoundSettings {
+                difficulty,
+                max_players,
+                tick_interval,
+            },
+            RocketJam::InLobby { .. },
+        ) => RocketJamRound {
+            difficulty: difficulty.unwrap_or(round.difficulty),
+            // never below the players already in the round
+            capacity: max_players
+                .unwrap_or(round.capacity)
+                .clamp(round.players.len().max(1), MAX_CAPACITY),
+            tick_interval: tick_interval
+                .unwrap_or(round.tick_interval)
+                .clamp(1, MAX_TICK_INTERVAL),
+            ..round.clone()
+        },
+        (ToBackend::LockLobby { locked }, RocketJam::InLobby { .. }) => RocketJamRound {
+            locked: *locked,
+            ..round.clone()
+        },
+        (ToBackend::ForceStart, RocketJam::InLobby { .. }) => {
+            info!("{:?} force started {:?}", user_id, round.id);
+            start_round(round, clock, catalog)
+        }
+        _ => return None,
+    };
+    Some(updated_round)
+}
+
 fn change_setting(
     user_id: UserId,
     item_id: ItemId,
     value: u8,
     round_state: &RoundState,
     round: &RocketJamRound,
-    current_tick: i32,
+    clock: Clock,
+    catalog: &ItemCatalog,
 ) -> RocketJamRound {
-    let mut new_state = 0;
+    match round_state
+        .items
+        .iter()
+        .find(|item| item.id == item_id && item.user_id == user_id)
+    {
+        None => {
+            warn!("User {:?} doesn't control item {:?}", user_id, item_id);
+            return round.clone();
+        }
+        Some(item) if value >= item.max_value => {
+            warn!(
+                "Value {:?} out of range for item {:?} with max value {:?}",
+                value, item_id, item.max_value
+            );
+            return round.clone();
+        }
+        _ => {}
+    }
+    let mut rng = round.rng.clone();
+    let mut player_stats = round_state.player_stats.clone();
+    player_stats.entry(user_id).or_default().changes += 1;
     let mut instructions_executed = round_state.instructions_executed;
-    let items = round_state
+    let mut level_instructions_executed = round_state.level_instructions_executed;
+    let instruction_ttl = instruction_ttl(round, round_state.level);
+    let items: Vec<Item> = round_state
         .items
         .iter()
         .map(|item| {
             if item.id == item_id && item.user_id == user_id {
-                new_state = value;
                 Item {
-                    state: new_state,
+                    state: value,
                     ..item.clone()
                 }
             } else {
@@ -453,9 +2372,23 @@ fn change_setting(
         .collect();
     let mut instructions: Vec<Instruction> = Vec::new();
     for instruction in &round_state.instructions {
-        if instruction.item_id == item_id && new_state == instruction.state {
+        if instruction.item_id == item_id && value == instruction.state {
+            // the hit goes to the player who changed the item, not the one who read it out
+            let stats = player_stats.entry(user_id).or_default();
+            stats.hits += 1;
+            if clock.now_ms >= instruction.issued_at_ms && instruction.issued_at_ms > 0 {
+                stats.reaction_ms_total += clock.now_ms - instruction.issued_at_ms;
+                stats.reactions += 1;
+            }
             instructions_executed += 1;
-            if let Some(instruction) = mk_instructions(instruction.user_id, &items, current_tick) {
+            level_instructions_executed += 1;
+            if let Some(instruction) = mk_instructions(
+                instruction.user_id,
+                &items,
+                clock,
+                instruction_ttl,
+                &mut rng,
+            ) {
                 instructions.push(instruction);
             } else {
                 error!("Got no instruction");
@@ -468,22 +2401,46 @@ fn change_setting(
     let new_round_state = RoundState {
         instructions,
         instructions_executed,
+        level_instructions_executed,
         items,
+        player_stats,
         ..round_state.clone()
     };
 
-    let game = RocketJam::InLevel(new_round_state);
+    let game = if level_instructions_executed < new_round_state.level_instructions_required {
+        RocketJam::InLevel(new_round_state)
+    } else if new_round_state.level + 1 < LEVELS.len() {
+        info!(
+            "Round {:?} completed level {:?}",
+            round.id,
+            new_round_state.level + 1
+        );
+        RocketJam::InLevel(RoundState {
+            instructions_executed,
+            instructions_missed: new_round_state.instructions_missed,
+            player_stats: new_round_state.player_stats,
+            ..mk_level(new_round_state.level + 1, round, clock, catalog, &mut rng)
+        })
+    } else {
+        info!("Round {:?} completed all levels", round.id);
+        RocketJam::Finished(RoundState {
+            completed: true,
+            ..new_round_state
+        })
+    };
     RocketJamRound {
         game,
+        rng,
         ..round.clone()
     }
 }
 
 fn toggle_ready(
     user_id: i32,
-    players_ready: &Vec<UserId>,
+    players_ready: &[UserId],
     round: &RocketJamRound,
-    current_tick: i32,
+    clock: Clock,
+    catalog: &ItemCatalog,
 ) -> RocketJamRound {
     let mut new_round = round.clone();
     if players_ready.contains(&user_id) {
@@ -493,87 +2450,212 @@ fn toggle_ready(
         new_round.game = RocketJam::InLobby { players_ready };
         new_round
     } else {
-        let mut players_ready = players_ready.clone();
+        let mut players_ready = players_ready.to_vec();
         info!("User {:?} wasn't ready, turning on", &user_id);
-        if players_ready.len() == (round.players.len() - 1) {
-            // everybody is ready
-            let mut rng = thread_rng();
-            let mut available_items: Vec<(ItemId, String, u8)> = ITEMS
-                .to_vec()
-                .iter()
-                .enumerate()
-                .map(|(item_id, label)| (item_id, label.to_string(), rng.gen_range(2, 10)))
-                .collect();
+        players_ready.push(user_id);
+        if everybody_ready(&round.players, &players_ready) {
+            start_round(round, clock, catalog)
+        } else {
+            new_round.game = RocketJam::InLobby { players_ready };
+            new_round
+        }
+    }
+}
 
-            available_items.shuffle(&mut rng);
+// Compares members, not counts, so a stale entry in players_ready can't start the round
+fn everybody_ready(players: &[UserId], players_ready: &[UserId]) -> bool {
+    !players.is_empty() && players.iter().all(|player| players_ready.contains(player))
+}
 
-            let items: Vec<Item> = round
-                .players
-                .clone()
-                .into_iter()
-                .map(|user_id| mk_items(user_id, 4, &mut available_items))
-                .flatten()
-                .collect();
-            let instructions: Vec<Instruction> = round
-                .players
+fn start_round(round: &RocketJamRound, clock: Clock, catalog: &ItemCatalog) -> RocketJamRound {
+    info!("Round {:?} starts with seed {:?}", round.id, round.seed);
+    let mut rng = round.rng.clone();
+    let game = RocketJam::InLevel(mk_level(0, round, clock, catalog, &mut rng));
+    RocketJamRound {
+        game,
+        rng,
+        ..round.clone()
+    }
+}
+
+// The items of a player who left are handed round to the remaining players, so
+// the instructions others already got for them can still be executed.
+fn remove_player(
+    user_id: UserId,
+    round: &RocketJamRound,
+    clock: Clock,
+    catalog: &ItemCatalog,
+) -> RocketJamRound {
+    let mut players = round.players.to_vec();
+    players.retain(|player_id| *player_id != user_id);
+    // the player who has been in the round longest takes over as host
+    let host = match players.first() {
+        Some(next_host) if round.host == user_id => {
+            info!("Host of {:?} passes to {:?}", round.id, next_host);
+            *next_host
+        }
+        _ => round.host,
+    };
+    let round = RocketJamRound {
+        players,
+        host,
+        ..round.clone()
+    };
+    if round.players.is_empty() {
+        return round;
+    }
+    match &round.game {
+        RocketJam::InLobby { players_ready } => {
+            let mut players_ready = players_ready.to_vec();
+            players_ready.retain(|player_id| *player_id != user_id);
+            if everybody_ready(&round.players, &players_ready) {
+                // the player everybody was waiting for left
+                start_round(&round, clock, catalog)
+            } else {
+                RocketJamRound {
+                    game: RocketJam::InLobby { players_ready },
+                    ..round
+                }
+            }
+        }
+        RocketJam::InLevel(round_state) => {
+            let mut heirs = round.players.iter().cycle();
+            let items: Vec<Item> = round_state
+                .items
                 .iter()
-                .map(|user_id| mk_instructions(user_id.clone(), &items, current_tick))
-                .flatten()
+                .map(|item| {
+                    if item.user_id != user_id {
+                        return item.clone();
+                    }
+                    match heirs.next() {
+                        Some(heir) => Item {
+                            user_id: *heir,
+                            ..item.clone()
+                        },
+                        None => item.clone(),
+                    }
+                })
                 .collect();
+            let mut instructions = round_state.instructions.to_vec();
+            instructions.retain(|instruction| instruction.user_id != user_id);
+            // fewer players need fewer instructions, but the level can't be done already
+            let level_instructions_required = (LEVELS[round_state.level].instructions_per_player
+                * round.players.len())
+            .max(round_state.level_instructions_executed + 1)
+            .min(round_state.level_instructions_required);
             let game = RocketJam::InLevel(RoundState {
                 items,
-                available_items,
                 instructions,
-                instructions_executed: 0,
-                instructions_missed: 0,
+                level_instructions_required,
+                ..round_state.clone()
             });
-            RocketJamRound {
-                game,
-                ..round.clone()
-            }
-        } else {
-            players_ready.push(user_id);
-            new_round.game = RocketJam::InLobby { players_ready };
-            new_round
+            RocketJamRound { game, ..round }
         }
+        RocketJam::Finished(_) => round,
     }
 }
 
-fn mk_instructions(user_id: UserId, items: &Vec<Item>, current_tick: i32) -> Option<Instruction> {
-    let mut rng = thread_rng();
-    let mut items: Vec<Item> = items.clone();
-    items.retain(|i| i.user_id != user_id);
-    items.shuffle(&mut rng);
+// Instruction lifetime in game loop ticks
+fn instruction_ttl(round: &RocketJamRound, level: usize) -> i32 {
+    let ttl = LEVELS[level].instruction_ttl;
+    let ttl = match round.difficulty {
+        Difficulty::Easy => ttl + ttl / 2,
+        Difficulty::Normal => ttl,
+        Difficulty::Hard => (ttl - 1).max(2),
+    };
+    ttl * round.tick_interval.max(1)
+}
+
+fn mk_level<R: Rng>(
+    level: usize,
+    round: &RocketJamRound,
+    clock: Clock,
+    catalog: &ItemCatalog,
+    rng: &mut R,
+) -> RoundState {
+    let config = &LEVELS[level];
+    let instruction_ttl = instruction_ttl(round, level);
+    let players = &round.players;
+    let deck = catalog
+        .deck(&round.deck)
+        .or_else(|| catalog.deck(&catalog.default_deck))
+        .unwrap();
+    let mut available_items = catalog.draw(
+        deck,
+        config.control_types,
+        players.len() * config.items_per_player,
+        rng,
+    );
+
+    let items: Vec<Item> = players
+        .iter()
+        .flat_map(|user_id| mk_items(*user_id, config.items_per_player, &mut available_items))
+        .collect();
+    let instructions: Vec<Instruction> = players
+        .iter()
+        .filter_map(|user_id| mk_instructions(*user_id, &items, clock, instruction_ttl, rng))
+        .collect();
+    RoundState {
+        level,
+        items,
+        instructions,
+        level_instructions_executed: 0,
+        level_instructions_required: config.instructions_per_player * players.len(),
+        instructions_executed: 0,
+        instructions_missed: 0,
+        player_stats: HashMap::new(),
+        completed: false,
+    }
+}
+
+fn mk_instructions<R: Rng>(
+    user_id: UserId,
+    all_items: &[Item],
+    clock: Clock,
+    instruction_ttl: i32,
+    rng: &mut R,
+) -> Option<Instruction> {
+    let mut items: Vec<Item> = all_items
+        .iter()
+        .filter(|i| i.user_id != user_id)
+        .cloned()
+        .collect();
+    // the last one left in a round has nobody to call out to but themselves
+    if items.is_empty() {
+        items = all_items.to_vec();
+    }
+    items.shuffle(rng);
 
     items
         .into_iter()
         .take(1)
         .map(|i| {
-            let new_state = if i.state == 0 { 1 } else { 0 };
+            // any value except the current one
+            let new_state = (i.state + rng.gen_range(1, i.max_value)) % i.max_value;
             Instruction {
                 item_id: i.id,
                 user_id,
                 state: new_state,
-                eol_tick: current_tick + 5,
+                eol_tick: clock.tick + instruction_ttl,
+                issued_at_ms: clock.now_ms,
             }
         })
         .next()
 }
 
-fn mk_items(
-    user_id: UserId,
-    count: usize,
-    available_items: &mut Vec<(ItemId, String, u8)>,
-) -> Vec<Item> {
+fn mk_items(user_id: UserId, count: usize, available_items: &mut Vec<CatalogItem>) -> Vec<Item> {
     let mut items = Vec::new();
     for _i in 0..count {
-        if let Some((item_id, label, max_value)) = available_items.pop() {
+        if let Some(catalog_item) = available_items.pop() {
             let item = Item {
-                id: item_id,
-                label,
+                // the remaining count keeps ids unique within the level
+                id: available_items.len(),
+                label: catalog_item.label,
                 state: 0,
                 user_id,
-                max_value,
+                control_type: catalog_item.control_type,
+                max_value: catalog_item.max_value,
+                instruction: catalog_item.instruction,
             };
             items.push(item);
         } else {
@@ -582,3 +2664,538 @@ fn mk_items(
     }
     items
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::chat::BlockList;
+
+    fn catalog() -> ItemCatalog {
+        ItemCatalog::load("catalog.json").unwrap()
+    }
+
+    fn user(id: UserId, role: Role) -> User {
+        User {
+            id,
+            username: format!("user{}", id),
+            hashed_password: String::new(),
+            role,
+        }
+    }
+
+    fn action(user_id: UserId, tick: i32, action: ToBackend) -> RoundEvent {
+        RoundEvent::Action {
+            user_id,
+            tick,
+            at_ms: 1_000 * tick as u64,
+            action,
+            host_override: false,
+        }
+    }
+
+    // two players start a round and play along for a while
+    fn round_log(seed: u64) -> Vec<RoundEvent> {
+        let mut events = vec![
+            RoundEvent::Created {
+                round_id: Uuid::from_u128(1),
+                user_id: 1,
+                deck: "classic".to_string(),
+                seed,
+                username: "user1".to_string(),
+                name: "Test".to_string(),
+                password_hash: None,
+                created_at_ms: 0,
+                private: false,
+                invite_code: None,
+                tick_interval: DEFAULT_TICK_INTERVAL,
+            },
+            RoundEvent::Joined {
+                user_id: 2,
+                username: "user2".to_string(),
+            },
+            action(1, 1, ToBackend::ToggleReady),
+            action(2, 1, ToBackend::ToggleReady),
+        ];
+        for tick in 2..40 {
+            let user_id = 1 + tick % 2;
+            let change = ToBackend::ChangeSetting {
+                item_id: (tick as usize * 7) % 12,
+                value: (tick % 3) as u8,
+            };
+            events.push(action(user_id, tick, change));
+            if tick % 5 == 0 {
+                events.push(RoundEvent::Tick {
+                    tick,
+                    at_ms: 1_000 * tick as u64,
+                });
+            }
+        }
+        events
+    }
+
+    fn replayed(events: &[RoundEvent]) -> serde_json::Value {
+        let round = replay(events, &catalog(), |_, _, _| {}).unwrap();
+        serde_json::to_value(round).unwrap()
+    }
+
+    #[test]
+    fn same_seed_and_events_replay_identically() {
+        let events = round_log(42);
+        let round = replayed(&events);
+        assert!(matches!(
+            round["game"],
+            serde_json::Value::Object(ref game) if game.contains_key("InLevel")
+        ));
+        assert_eq!(replayed(&events), round);
+        assert_ne!(replayed(&round_log(43)), round);
+    }
+
+    fn level_items(round: &RocketJamRound) -> Vec<Item> {
+        match &round.game {
+            RocketJam::InLevel(round_state) => round_state.items.clone(),
+            game => panic!("Not in a level: {:?}", game),
+        }
+    }
+
+    #[test]
+    fn settings_stay_below_the_max_value() {
+        let events = round_log(42)[..4].to_vec();
+        let round = replay(&events, &catalog(), |_, _, _| {}).unwrap();
+        let items = level_items(&round);
+        let item = items.iter().find(|item| item.user_id == 1).unwrap();
+        let change = |value| {
+            let change = ToBackend::ChangeSetting {
+                item_id: item.id,
+                value,
+            };
+            let changed = apply_event(&round, &action(1, 2, change), &catalog());
+            level_items(&changed)
+                .into_iter()
+                .find(|i| i.id == item.id)
+                .unwrap()
+                .state
+        };
+        assert_eq!(change(item.max_value), item.state);
+        assert_eq!(change(u8::MAX), item.state);
+        assert_eq!(change(item.max_value - 1), item.max_value - 1);
+    }
+
+    #[test]
+    fn instructions_never_ask_for_the_current_value() {
+        let clock = Clock { tick: 0, now_ms: 0 };
+        for (control_type, max_value) in [
+            (ControlType::Switch, 2),
+            (ControlType::Dial, 3),
+            (ControlType::Slider, 9),
+        ] {
+            for state in 0..max_value {
+                let items = vec![Item {
+                    id: 0,
+                    label: "Toaster".to_string(),
+                    state,
+                    user_id: 2,
+                    control_type,
+                    max_value,
+                    instruction: String::new(),
+                }];
+                for seed in 0..50 {
+                    let mut rng = Pcg32::seed_from_u64(seed);
+                    let instruction = mk_instructions(1, &items, clock, 3, &mut rng).unwrap();
+                    assert_ne!(instruction.state, state);
+                    assert!(instruction.state < max_value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn force_started_solo_round_gets_instructions() {
+        let events = vec![round_log(42).remove(0), action(1, 1, ToBackend::ForceStart)];
+        let round = replay(&events, &catalog(), |_, _, _| {}).unwrap();
+        match round.game {
+            RocketJam::InLevel(round_state) => {
+                assert_eq!(round_state.instructions.len(), 1);
+                assert_eq!(round_state.instructions[0].user_id, 1);
+            }
+            game => panic!("Round didn't start: {:?}", game),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_admins_pick_the_seed() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        let start_game = ToBackend::StartGame {
+            deck: None,
+            seed: Some(42),
+            name: None,
+            password: None,
+            private: false,
+        };
+        app.update(&user(1, Role::Admin), start_game.clone()).await;
+        app.update(&user(2, Role::Moderator), start_game).await;
+        let model = app.model.read().await;
+        let seed_of = |user_id| model.games_by_id[&model.game_ids_by_user_id[&user_id]].seed;
+        assert_eq!(seed_of(1), 42);
+        assert_ne!(seed_of(2), 42);
+    }
+
+    #[tokio::test]
+    async fn moderators_act_as_host_only_when_asking_to() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        let start_game = ToBackend::StartGame {
+            deck: None,
+            seed: None,
+            name: None,
+            password: None,
+            private: false,
+        };
+        app.update(&user(1, Role::Player), start_game).await;
+        let round_id = app.round_id_for_user(1).await.unwrap();
+        for (user_id, role) in [(2, Role::Moderator), (3, Role::Player)] {
+            let join_game = ToBackend::JoinGame {
+                round_id: round_id.clone(),
+                password: None,
+            };
+            app.update(&user(user_id, role), join_game).await;
+        }
+        let in_lobby = || async {
+            let model = app.model.read().await;
+            matches!(model.games_by_id[&round_id].game, RocketJam::InLobby { .. })
+        };
+        app.update(&user(2, Role::Moderator), ToBackend::ForceStart)
+            .await;
+        assert!(in_lobby().await);
+        app.moderate(&user(3, Role::Player), ToBackend::ForceStart)
+            .await;
+        assert!(in_lobby().await);
+        app.moderate(&user(2, Role::Moderator), ToBackend::ForceStart)
+            .await;
+        assert!(!in_lobby().await);
+    }
+
+    #[tokio::test]
+    async fn only_round_actions_go_into_the_log() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        let start_game = ToBackend::StartGame {
+            deck: None,
+            seed: None,
+            name: None,
+            password: Some("secret".to_string()),
+            private: false,
+        };
+        app.update(&user(1, Role::Player), start_game.clone()).await;
+        let round_id = app.round_id_for_user(1).await.unwrap();
+        let join_game = ToBackend::JoinGame {
+            round_id: Uuid::new_v4().to_string(),
+            password: Some("secret".to_string()),
+        };
+        let join_by_code = ToBackend::JoinByInviteCode {
+            invite_code: "NOVA-0000AA".to_string(),
+        };
+        for msg in [start_game, join_game, join_by_code, ToBackend::Init] {
+            let msgs = app.update(&user(1, Role::Player), msg).await;
+            assert!(matches!(msgs[..], [(1, ToClient::UpdateGameState { .. })]));
+        }
+        app.update(&user(1, Role::Player), ToBackend::ToggleReady)
+            .await;
+        let log = app.round_log(&round_id).await.unwrap();
+        assert!(matches!(
+            log[..],
+            [
+                RoundEvent::Created { .. },
+                RoundEvent::Action {
+                    action: ToBackend::ToggleReady,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn disconnected_users_leave_the_queue() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        for user_id in [1, 2, 3] {
+            let quick_play = ToBackend::QuickPlay {
+                preferred_size: Some(4),
+            };
+            app.update(&user(user_id, Role::Player), quick_play).await;
+        }
+        let msgs = app.prune_disconnected(&HashSet::from([1, 3])).await;
+        let queued: Vec<UserId> = app
+            .model
+            .read()
+            .await
+            .queue
+            .iter()
+            .map(|entry| entry.user_id)
+            .collect();
+        assert_eq!(queued, vec![1, 3]);
+        assert!(matches!(
+            msgs[..],
+            [
+                (
+                    1,
+                    ToClient::QueuePosition {
+                        position: Some(1),
+                        queue_length: 2
+                    }
+                ),
+                (
+                    3,
+                    ToClient::QueuePosition {
+                        position: Some(2),
+                        queue_length: 2
+                    }
+                ),
+            ]
+        ));
+        assert!(app
+            .prune_disconnected(&HashSet::from([1, 3]))
+            .await
+            .is_empty());
+    }
+
+    // (user id, preferred size, queued at) of each in line
+    fn queue(entries: &[(UserId, usize, u64)]) -> Vec<QueueEntry> {
+        entries
+            .iter()
+            .map(|&(user_id, preferred_size, queued_at_ms)| QueueEntry {
+                user_id,
+                username: format!("user{}", user_id),
+                preferred_size,
+                queued_at_ms,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn next_match_prefers_the_same_size_then_takes_anyone() {
+        // the queue, now, who gets matched
+        let cases = [
+            (queue(&[]), 0, None),
+            (queue(&[(1, 2, 0)]), 10 * MATCH_WAIT_MS, None),
+            (queue(&[(1, 3, 0), (2, 4, 0)]), 1_000, None),
+            (
+                queue(&[(1, 3, 0), (2, 2, 0), (3, 3, 0), (4, 3, 0)]),
+                0,
+                Some(vec![1, 3, 4]),
+            ),
+            (
+                queue(&[(1, 3, 0), (2, 2, 0), (3, 2, 0)]),
+                1_000,
+                Some(vec![2, 3]),
+            ),
+            (
+                queue(&[(1, 3, 0), (2, 4, 5_000)]),
+                MATCH_WAIT_MS,
+                Some(vec![1, 2]),
+            ),
+            (
+                queue(&[(1, 2, 0), (2, 3, 0), (3, 4, 0)]),
+                MATCH_WAIT_MS,
+                Some(vec![1, 2]),
+            ),
+            (
+                queue(&[(1, 4, 1_000), (2, 3, 0), (3, 4, 1_000)]),
+                MATCH_WAIT_MS,
+                Some(vec![2, 1, 3]),
+            ),
+        ];
+        for (queue, now_ms, expected) in cases {
+            assert_eq!(next_match(&queue, now_ms), expected, "{:?}", queue);
+        }
+    }
+
+    #[tokio::test]
+    async fn members_of_a_round_open_no_other() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        let settings = || RoundSettings {
+            deck: None,
+            name: None,
+            password: None,
+            private: false,
+        };
+        let mut model = app.model.write().await;
+        let round = open_round(1, "user1", settings(), 1, &mut model, &catalog()).unwrap();
+        assert!(open_round(1, "user1", settings(), 2, &mut model, &catalog()).is_none());
+        assert_eq!(model.games_by_id.len(), 1);
+        assert_eq!(model.game_ids_by_user_id[&1], round.id.to_string());
+    }
+
+    #[tokio::test]
+    async fn guessing_invite_codes_is_rate_limited() {
+        let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+        let start_game = ToBackend::StartGame {
+            deck: None,
+            seed: None,
+            name: None,
+            password: None,
+            private: true,
+        };
+        app.update(&user(1, Role::Player), start_game).await;
+        let invite_code = {
+            let model = app.model.read().await;
+            model.games_by_id[&model.game_ids_by_user_id[&1]]
+                .invite_code
+                .clone()
+                .unwrap()
+        };
+        let join = |invite_code: &str| ToBackend::JoinByInviteCode {
+            invite_code: invite_code.to_string(),
+        };
+        for _ in 0..INVITE_CODE_TRY_LIMIT {
+            let msgs = app
+                .update(&user(2, Role::Player), join("NOVA-0000AA"))
+                .await;
+            assert!(matches!(msgs[..], [(2, ToClient::JoinFailed { .. })]));
+        }
+        // even the right code is turned away until the window passes
+        app.update(&user(2, Role::Player), join(&invite_code)).await;
+        assert!(!app.model.read().await.game_ids_by_user_id.contains_key(&2));
+        app.update(&user(3, Role::Player), join(&invite_code)).await;
+        assert!(app.model.read().await.game_ids_by_user_id.contains_key(&3));
+    }
+
+    #[derive(Clone, Debug)]
+    enum MembershipOp {
+        StartGame(UserId),
+        // the round is picked by its position among the open ones
+        Join(UserId, usize),
+        Spectate(UserId, usize),
+        Leave(UserId),
+        Kick(UserId, UserId),
+        ToggleReady(UserId),
+        ForceStart(UserId),
+    }
+
+    fn membership_op() -> impl Strategy<Value = MembershipOp> {
+        let user_id = 1..=6;
+        let round = 0..3usize;
+        prop_oneof![
+            user_id.clone().prop_map(MembershipOp::StartGame),
+            (user_id.clone(), round.clone()).prop_map(|(u, r)| MembershipOp::Join(u, r)),
+            (user_id.clone(), round).prop_map(|(u, r)| MembershipOp::Spectate(u, r)),
+            user_id.clone().prop_map(MembershipOp::Leave),
+            (user_id.clone(), user_id.clone()).prop_map(|(u, t)| MembershipOp::Kick(u, t)),
+            user_id.clone().prop_map(MembershipOp::ToggleReady),
+            user_id.prop_map(MembershipOp::ForceStart),
+        ]
+    }
+
+    fn check_membership(model: &Model) -> Result<(), String> {
+        for (round_id, round) in &model.games_by_id {
+            let mut members = round.members();
+            let member_count = members.len();
+            members.sort_unstable();
+            members.dedup();
+            if members.len() != member_count {
+                return Err(format!("{} has duplicate members: {:?}", round_id, round));
+            }
+            if !round.players.contains(&round.host) {
+                return Err(format!("{}'s host isn't one of its players", round_id));
+            }
+            if round.players.len() > round.capacity {
+                return Err(format!("{} is over capacity", round_id));
+            }
+            for user_id in &members {
+                if model.game_ids_by_user_id.get(user_id) != Some(round_id) {
+                    return Err(format!("{} isn't mapped to {}", user_id, round_id));
+                }
+            }
+            match &round.game {
+                RocketJam::InLobby { players_ready } => {
+                    if players_ready.iter().any(|u| !round.players.contains(u)) {
+                        return Err(format!("{} has ready players not in it", round_id));
+                    }
+                }
+                RocketJam::InLevel(round_state) => {
+                    if round_state
+                        .items
+                        .iter()
+                        .any(|item| !round.players.contains(&item.user_id))
+                    {
+                        return Err(format!("{} has items of departed players", round_id));
+                    }
+                    for player in &round.players {
+                        let count = round_state
+                            .instructions
+                            .iter()
+                            .filter(|instruction| instruction.user_id == *player)
+                            .count();
+                        if count != 1 {
+                            return Err(format!(
+                                "{} has {} instructions in {}",
+                                player, count, round_id
+                            ));
+                        }
+                    }
+                }
+                RocketJam::Finished(_) => {}
+            }
+        }
+        for (user_id, round_id) in &model.game_ids_by_user_id {
+            match model.games_by_id.get(round_id) {
+                Some(round) if round.members().contains(user_id) => {}
+                _ => return Err(format!("{} is mapped to {}, not in it", user_id, round_id)),
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply_op(app: &RocketJamApp, op: &MembershipOp) {
+        let round_id = |index: usize| async move {
+            let model = app.model.read().await;
+            let mut round_ids: Vec<RoundId> = model.games_by_id.keys().cloned().collect();
+            round_ids.sort();
+            round_ids.get(index % round_ids.len().max(1)).cloned()
+        };
+        let (user_id, msg) = match op {
+            MembershipOp::StartGame(u) => (
+                *u,
+                ToBackend::StartGame {
+                    deck: None,
+                    seed: None,
+                    name: None,
+                    password: None,
+                    private: false,
+                },
+            ),
+            MembershipOp::Join(u, index) => match round_id(*index).await {
+                Some(round_id) => (
+                    *u,
+                    ToBackend::JoinGame {
+                        round_id,
+                        password: None,
+                    },
+                ),
+                None => return,
+            },
+            MembershipOp::Spectate(u, index) => match round_id(*index).await {
+                Some(round_id) => (*u, ToBackend::Spectate { round_id }),
+                None => return,
+            },
+            MembershipOp::Leave(u) => (*u, ToBackend::LeaveRound),
+            MembershipOp::Kick(u, target) => (*u, ToBackend::Kick { user_id: *target }),
+            MembershipOp::ToggleReady(u) => (*u, ToBackend::ToggleReady),
+            MembershipOp::ForceStart(u) => (*u, ToBackend::ForceStart),
+        };
+        app.update(&user(user_id, Role::Player), msg).await;
+    }
+
+    proptest! {
+        #[test]
+        fn membership_stays_consistent(ops in proptest::collection::vec(membership_op(), 1..60)) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let app = RocketJamApp::new(catalog(), Box::new(BlockList::new(vec![])));
+            for op in &ops {
+                let result = runtime.block_on(async {
+                    apply_op(&app, op).await;
+                    check_membership(&*app.model.read().await)
+                });
+                prop_assert!(result.is_ok(), "after {:?}: {}", op, result.unwrap_err());
+            }
+        }
+    }
+}
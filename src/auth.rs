@@ -0,0 +1,66 @@
+use tracing::warn;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+use crate::{
+    env::Env,
+    user::{Role, User},
+};
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+#[derive(Debug)]
+struct Forbidden;
+
+impl Reject for Forbidden {}
+
+// Resolves `Authorization: Bearer <token>` to the logged in user, who needs
+// at least the `required` role. The role is looked up on each request, so
+// a demotion takes effect right away. Pair with `recover(handle_rejection)`.
+pub fn with_role(
+    env: Env,
+    required: Role,
+) -> impl Filter<Extract = (User,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(
+        move |authorization: Option<String>| {
+            let env = env.clone();
+            async move {
+                let token = authorization
+                    .as_deref()
+                    .and_then(|authorization| authorization.strip_prefix("Bearer "))
+                    .map(String::from)
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+                let client = env
+                    .client_broadcaster
+                    .get(&token)
+                    .await
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+                let user = env
+                    .user_service
+                    .find_user(client.user_id)
+                    .await
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+                if user.role < required {
+                    warn!(
+                        "User {:?} is a {:?}, needs to be a {:?}",
+                        user.id, user.role, required
+                    );
+                    return Err(warp::reject::custom(Forbidden));
+                }
+                Ok(user)
+            }
+        },
+    )
+}
+
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(StatusCode::UNAUTHORIZED)
+    } else if rejection.find::<Forbidden>().is_some() {
+        Ok(StatusCode::FORBIDDEN)
+    } else {
+        Err(rejection)
+    }
+}
@@ -1,46 +1,115 @@
 use crate::{app::ToBackend, env::Env};
-use log::{error, info};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::{
+    sync::{mpsc::Receiver, Mutex},
+    task::JoinHandle,
+};
+use tracing::{error, field, info, info_span, Instrument};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ToBackendEnvelope {
     token: String,
     to_backend: ToBackend,
+    // set from the request's x-request-id header, see action_handler
+    #[serde(default)]
+    pub request_id: Option<String>,
+    // act as the host of the sender's round, see RocketJamApp::moderate
+    #[serde(default)]
+    pub as_moderator: bool,
+}
+
+impl ToBackendEnvelope {
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    // what to log of it, actions can carry passwords
+    pub fn name(&self) -> &'static str {
+        self.to_backend.name()
+    }
 }
 
 pub struct Processor {
     env: Env,
-    receiver: tokio::sync::mpsc::Receiver<ToBackendEnvelope>,
+    // shared, so a processor restarted after a panic picks up the queued actions
+    receiver: Arc<Mutex<Receiver<ToBackendEnvelope>>>,
 }
 
 impl Processor {
-    pub fn new(env: Env, receiver: tokio::sync::mpsc::Receiver<ToBackendEnvelope>) -> Self {
+    pub fn new(env: Env, receiver: Arc<Mutex<Receiver<ToBackendEnvelope>>>) -> Self {
         Processor { env, receiver }
     }
 
-    pub fn start_loop(mut self) {
+    pub fn start_loop(self) -> JoinHandle<()> {
         tokio::spawn(async move {
-            while let Some(action) = self.receiver.recv().await {
-                info!("Processing action {:?}", action);
-                if let Some(client) = self.env.client_broadcaster.get(&action.token).await {
-                    let user_by_id = self.env.user_service.find_user(client.user_id).await;
-                    match user_by_id {
-                        None => error!("Client references missing user {:?}", client.user_id),
-                        Some(user) => {
-                            let to_clients = self.env.app.update(&user, action.to_backend);
-                            for client_message in to_clients.await {
+            loop {
+                let action = match self.receiver.lock().await.recv().await {
+                    Some(action) => action,
+                    None => break,
+                };
+                self.env.metrics.queue_depth.dec();
+                let span = info_span!(
+                    "action",
+                    action = %action.to_backend.name(),
+                    request_id = %action.request_id.as_deref().unwrap_or_default(),
+                    // enough to tell clients apart without leaking the token
+                    token = %action.token.chars().take(8).collect::<String>(),
+                    user_id = field::Empty,
+                    round_id = field::Empty,
+                );
+                self.process(action).instrument(span).await;
+            }
+            info!("I'm done here.");
+        })
+    }
+
+    async fn process(&self, action: ToBackendEnvelope) {
+        if let Some(client) = self.env.client_broadcaster.get(&action.token).await {
+            let span = tracing::Span::current();
+            span.record("user_id", &client.user_id);
+            if let Some(round_id) = self.env.app.round_id_for_user(client.user_id).await {
+                span.record("round_id", &round_id.as_str());
+            }
+            info!("Processing action {}", action.name());
+            if action.to_backend == ToBackend::Resync {
+                self.env.client_broadcaster.resync(&action.token).await;
+            }
+            let user_by_id = self.env.user_service.find_user(client.user_id).await;
+            match user_by_id {
+                None => error!("Client references missing user {:?}", client.user_id),
+                Some(user) => {
+                    self.env
+                        .metrics
+                        .actions
+                        .with_label_values(&[action.to_backend.name()])
+                        .inc();
+                    let to_clients = if action.as_moderator {
+                        info!("User {:?} acts as moderator", user.id);
+                        self.env.app.moderate(&user, action.to_backend).await
+                    } else {
+                        self.env.app.update(&user, action.to_backend).await
+                    };
+                    for client_message in to_clients {
+                        match &action.request_id {
+                            Some(request_id) => {
+                                self.env
+                                    .client_broadcaster
+                                    .send_to_user_for_request(client_message, request_id)
+                                    .await
+                            }
+                            None => {
                                 self.env
                                     .client_broadcaster
                                     .send_to_user(client_message)
-                                    .await;
+                                    .await
                             }
                         }
                     }
-                } else {
-                    error!("Couldn't find client for token {:?}", action.token);
                 }
             }
-            info!("I'm done here.");
-        });
+        } else {
+            error!("Couldn't find client for token {:?}", action.token);
+        }
     }
 }
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use rand::{prelude::SliceRandom, Rng};
+use serde::Deserialize;
+
+use crate::app::ControlType;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ItemDefinition {
+    pub label: String,
+    pub control_type: ControlType,
+    #[serde(default)]
+    pub max_value: Option<u8>,
+    // e.g. "Crank the {label} up to {value}"
+    #[serde(default)]
+    pub instruction: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Deck {
+    pub name: String,
+    pub items: Vec<ItemDefinition>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ItemCatalog {
+    pub default_deck: String,
+    #[serde(default)]
+    pub modifiers: Vec<String>,
+    pub decks: Vec<Deck>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatalogItem {
+    pub label: String,
+    pub control_type: ControlType,
+    pub max_value: u8,
+    pub instruction: String,
+}
+
+impl ItemCatalog {
+    pub fn load(path: &str) -> Result<ItemCatalog, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read item catalog {:?}: {}", path, e))?;
+        let catalog: ItemCatalog = serde_json::from_str(&json)
+            .map_err(|e| format!("Can't parse item catalog {:?}: {}", path, e))?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.deck(&self.default_deck).is_none() {
+            return Err(format!("Default deck {:?} not found", self.default_deck));
+        }
+        for deck in &self.decks {
+            let mut labels = HashSet::new();
+            for item in &deck.items {
+                if !labels.insert(&item.label) {
+                    return Err(format!(
+                        "Duplicate item {:?} in deck {:?}",
+                        item.label, deck.name
+                    ));
+                }
+                if let Some(max_value) = item.max_value {
+                    if !item.control_type.max_values().contains(&max_value) {
+                        return Err(format!(
+                            "Item {:?} in deck {:?} has unsupported max value {:?}",
+                            item.label, deck.name, max_value
+                        ));
+                    }
+                }
+            }
+            // every level must be able to find items for its control types
+            for control_type in [ControlType::Switch, ControlType::Dial, ControlType::Slider] {
+                if !deck.items.iter().any(|i| i.control_type == control_type) {
+                    return Err(format!(
+                        "Deck {:?} has no item of type {:?}",
+                        deck.name, control_type
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn deck(&self, name: &str) -> Option<&Deck> {
+        self.decks.iter().find(|deck| deck.name == name)
+    }
+
+    pub fn deck_names(&self) -> Vec<String> {
+        self.decks.iter().map(|deck| deck.name.clone()).collect()
+    }
+
+    // Draws `count` items with unique labels. Once the deck's items are used up,
+    // variants like "Backup Fridge" are made from the catalog's modifiers.
+    pub fn draw<R: Rng>(
+        &self,
+        deck: &Deck,
+        control_types: &[ControlType],
+        count: usize,
+        rng: &mut R,
+    ) -> Vec<CatalogItem> {
+        let mut candidates: Vec<&ItemDefinition> = deck
+            .items
+            .iter()
+            .filter(|i| control_types.contains(&i.control_type))
+            .collect();
+        if candidates.is_empty() {
+            return vec![];
+        }
+        candidates.shuffle(rng);
+        let mut modifiers = self.modifiers.clone();
+        modifiers.shuffle(rng);
+
+        let mut drawn = Vec::new();
+        let mut variant = 0;
+        while drawn.len() < count {
+            for definition in candidates.iter().take(count - drawn.len()) {
+                let label = variant_label(&definition.label, variant, &modifiers);
+                let max_value = match definition.max_value {
+                    Some(max_value) => max_value,
+                    None => *definition.control_type.max_values().choose(rng).unwrap(),
+                };
+                let instruction = match &definition.instruction {
+                    Some(instruction) => instruction.clone(),
+                    None => definition.control_type.default_instruction().to_string(),
+                };
+                drawn.push(CatalogItem {
+                    label,
+                    control_type: definition.control_type,
+                    max_value,
+                    instruction,
+                });
+            }
+            variant += 1;
+        }
+        drawn.shuffle(rng);
+        drawn
+    }
+}
+
+fn variant_label(label: &str, variant: usize, modifiers: &[String]) -> String {
+    if variant == 0 {
+        label.to_string()
+    } else if modifiers.is_empty() {
+        format!("{} {}", label, variant + 1)
+    } else {
+        let modifier = &modifiers[(variant - 1) % modifiers.len()];
+        match (variant - 1) / modifiers.len() {
+            0 => format!("{} {}", modifier, label),
+            n => format!("{} {} {}", modifier, label, n + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_pcg::Pcg32;
+
+    use super::*;
+
+    const ALL_CONTROL_TYPES: [ControlType; 3] =
+        [ControlType::Switch, ControlType::Dial, ControlType::Slider];
+
+    fn catalog() -> ItemCatalog {
+        ItemCatalog::load("catalog.json").unwrap()
+    }
+
+    fn assert_drawn_items_are_valid(catalog: &ItemCatalog) {
+        let smallest = catalog
+            .decks
+            .iter()
+            .min_by_key(|deck| deck.items.len())
+            .unwrap();
+        // 8 players with 6 items each, more than any deck holds
+        let drawn = catalog.draw(
+            smallest,
+            &ALL_CONTROL_TYPES,
+            8 * 6,
+            &mut Pcg32::seed_from_u64(1),
+        );
+        assert_eq!(drawn.len(), 8 * 6);
+        let labels: HashSet<&String> = drawn.iter().map(|item| &item.label).collect();
+        assert_eq!(labels.len(), drawn.len(), "{:?}", labels);
+        for item in &drawn {
+            assert!(
+                item.control_type.max_values().contains(&item.max_value),
+                "{:?}",
+                item
+            );
+        }
+    }
+
+    #[test]
+    fn large_rounds_draw_unique_valid_items_from_the_smallest_deck() {
+        assert_drawn_items_are_valid(&catalog());
+    }
+
+    #[test]
+    fn variants_are_numbered_without_modifiers() {
+        let catalog = ItemCatalog {
+            modifiers: vec![],
+            ..catalog()
+        };
+        assert_drawn_items_are_valid(&catalog);
+        assert_eq!(variant_label("Fridge", 2, &[]), "Fridge 3");
+    }
+
+    #[test]
+    fn variants_go_through_the_modifiers_then_number_them() {
+        let modifiers = vec!["Backup".to_string(), "Spare".to_string()];
+        let labels: Vec<String> = (0..5)
+            .map(|variant| variant_label("Fridge", variant, &modifiers))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Fridge",
+                "Backup Fridge",
+                "Spare Fridge",
+                "Backup Fridge 2",
+                "Spare Fridge 2"
+            ]
+        );
+    }
+
+    fn deck(items: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({ "name": "test", "items": items })
+    }
+
+    fn validate(catalog: serde_json::Value) -> Result<(), String> {
+        serde_json::from_value::<ItemCatalog>(catalog)
+            .unwrap()
+            .validate()
+    }
+
+    #[test]
+    fn validate_rejects_broken_catalogs() {
+        let switch = serde_json::json!({ "label": "Toaster", "control_type": "Switch" });
+        let dial = serde_json::json!({ "label": "Oven", "control_type": "Dial" });
+        let slider = serde_json::json!({ "label": "Mixer", "control_type": "Slider" });
+        let cases = [
+            (
+                serde_json::json!({
+                    "default_deck": "missing",
+                    "decks": [deck(serde_json::json!([switch, dial, slider]))]
+                }),
+                "Default deck \"missing\" not found",
+            ),
+            (
+                serde_json::json!({
+                    "default_deck": "test",
+                    "decks": [deck(serde_json::json!([switch, switch, dial, slider]))]
+                }),
+                "Duplicate item \"Toaster\" in deck \"test\"",
+            ),
+            (
+                serde_json::json!({
+                    "default_deck": "test",
+                    "decks": [deck(serde_json::json!([
+                        switch,
+                        { "label": "Oven", "control_type": "Dial", "max_value": 4 },
+                        slider
+                    ]))]
+                }),
+                "Item \"Oven\" in deck \"test\" has unsupported max value 4",
+            ),
+            (
+                serde_json::json!({
+                    "default_deck": "test",
+                    "decks": [deck(serde_json::json!([switch, dial]))]
+                }),
+                "Deck \"test\" has no item of type Slider",
+            ),
+        ];
+        for (catalog, error) in cases {
+            assert_eq!(validate(catalog), Err(error.to_string()));
+        }
+        assert_eq!(
+            validate(serde_json::json!({
+                "default_deck": "test",
+                "decks": [deck(serde_json::json!([switch, dial, slider]))]
+            })),
+            Ok(())
+        );
+    }
+}
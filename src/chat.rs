@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::user::UserId;
+
+pub const MAX_CHAT_LENGTH: usize = 200;
+// messages kept per round and for the lobby, sent to whoever joins
+pub const CHAT_HISTORY_LENGTH: usize = 20;
+// at most this many messages per user within the window
+pub const CHAT_RATE_LIMIT: usize = 5;
+pub const CHAT_RATE_WINDOW_MS: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Emote {
+    Hurry,
+    Help,
+    NiceOne,
+    Oops,
+    Wait,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ChatBody {
+    Text(String),
+    Emote(Emote),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatEntry {
+    pub user_id: UserId,
+    pub username: String,
+    pub body: ChatBody,
+    pub sent_at_ms: u64,
+}
+
+// Decides what of a chat message gets through, None drops it altogether
+pub trait ChatFilter: Send + Sync {
+    fn filter(&self, text: &str) -> Option<String>;
+}
+
+// Masks blocked words, matching whole words regardless of case
+pub struct BlockList {
+    words: HashSet<String>,
+}
+
+impl BlockList {
+    pub fn new(words: Vec<String>) -> Self {
+        BlockList {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+
+    // one word per line, lines starting with # are ignored
+    pub fn load(path: &str) -> Result<BlockList, String> {
+        let list = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read block list {:?}: {}", path, e))?;
+        let words = list
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        Ok(BlockList::new(words))
+    }
+}
+
+impl ChatFilter for BlockList {
+    fn filter(&self, text: &str) -> Option<String> {
+        let filtered = text
+            .split(' ')
+            .map(|word| {
+                let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+                if self.words.contains(&bare.to_lowercase()) {
+                    word.replace(bare, &"*".repeat(bare.chars().count()))
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        Some(filtered)
+    }
+}
+
+// Trims and shortens a text message, None if nothing is left to send
+pub fn clean_text(text: &str, filter: &dyn ChatFilter) -> Option<String> {
+    let text: String = text.trim().chars().take(MAX_CHAT_LENGTH).collect();
+    if text.is_empty() {
+        return None;
+    }
+    filter.filter(&text)
+}
+
+// Drops the send times that left the window and records `now_ms` if the
+// user is still below the limit
+pub fn within_rate_limit(
+    sent_at_ms: &mut Vec<u64>,
+    now_ms: u64,
+    limit: usize,
+    window_ms: u64,
+) -> bool {
+    sent_at_ms.retain(|sent| now_ms.saturating_sub(*sent) < window_ms);
+    if sent_at_ms.len() >= limit {
+        return false;
+    }
+    sent_at_ms.push(now_ms);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_list() -> BlockList {
+        BlockList::new(vec!["Darn".to_string(), "heck".to_string()])
+    }
+
+    #[test]
+    fn rate_limit_lets_messages_through_once_they_leave_the_window() {
+        let mut sent_at_ms = Vec::new();
+        for i in 0..CHAT_RATE_LIMIT as u64 {
+            assert!(within_rate_limit(
+                &mut sent_at_ms,
+                1_000 + i,
+                CHAT_RATE_LIMIT,
+                CHAT_RATE_WINDOW_MS
+            ));
+        }
+        assert!(!within_rate_limit(
+            &mut sent_at_ms,
+            2_000,
+            CHAT_RATE_LIMIT,
+            CHAT_RATE_WINDOW_MS
+        ));
+        // refused messages don't count, the first send leaves the window first
+        assert_eq!(sent_at_ms.len(), CHAT_RATE_LIMIT);
+        assert!(!within_rate_limit(
+            &mut sent_at_ms,
+            1_000 + CHAT_RATE_WINDOW_MS - 1,
+            CHAT_RATE_LIMIT,
+            CHAT_RATE_WINDOW_MS
+        ));
+        assert!(within_rate_limit(
+            &mut sent_at_ms,
+            1_000 + CHAT_RATE_WINDOW_MS,
+            CHAT_RATE_LIMIT,
+            CHAT_RATE_WINDOW_MS
+        ));
+        assert!(!within_rate_limit(
+            &mut sent_at_ms,
+            1_000 + CHAT_RATE_WINDOW_MS,
+            CHAT_RATE_LIMIT,
+            CHAT_RATE_WINDOW_MS
+        ));
+    }
+
+    #[test]
+    fn clean_text_trims_and_truncates() {
+        let filter = block_list();
+        assert_eq!(
+            clean_text("  hi there \n", &filter),
+            Some("hi there".to_string())
+        );
+        assert_eq!(clean_text(" \t ", &filter), None);
+        let long = "é".repeat(MAX_CHAT_LENGTH + 10);
+        let cleaned = clean_text(&long, &filter).unwrap();
+        assert_eq!(cleaned.chars().count(), MAX_CHAT_LENGTH);
+    }
+
+    #[test]
+    fn block_list_masks_whole_words_in_any_case() {
+        let filter = block_list();
+        let cases = [
+            ("darn it", "**** it"),
+            ("DARN it", "**** it"),
+            ("Oh, heck!", "Oh, ****!"),
+            ("\"Heck\"?", "\"****\"?"),
+            ("darned heckler", "darned heckler"),
+            ("no blocked words", "no blocked words"),
+        ];
+        for (text, filtered) in cases {
+            assert_eq!(filter.filter(text), Some(filtered.to_string()), "{}", text);
+        }
+    }
+}
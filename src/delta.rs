@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+
+use crate::app::{ClientState, ItemId, ToClient};
+
+// a full snapshot every so many deltas, should a client have misapplied one
+const SNAPSHOT_INTERVAL: u32 = 30;
+
+// The parts of a ClientState::InGame that changed, None for those that didn't
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InGameDelta {
+    current_instruction: Option<String>,
+    // only the items whose state changed
+    item_states: Vec<ItemState>,
+    instructions_executed: Option<usize>,
+    instructions_missed: Option<usize>,
+    level_progress: Option<usize>,
+    level_target: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ItemState {
+    id: ItemId,
+    state: u8,
+}
+
+// The level a connection was last sent, deltas build on it. Everything but
+// in-level states passes through as it is.
+#[derive(Default)]
+pub struct DeltaTracker {
+    seq: u64,
+    base: Option<ClientState>,
+    deltas_since_snapshot: u32,
+}
+
+impl DeltaTracker {
+    // `to_client` as the connection should get it, None if nothing changed for it
+    pub fn track(&mut self, to_client: ToClient) -> Option<ToClient> {
+        let client_state = match to_client {
+            ToClient::UpdateGameState { client_state } => client_state,
+            ToClient::EnterRound { .. } => {
+                self.base = None;
+                return Some(to_client);
+            }
+            to_client => return Some(to_client),
+        };
+        if !matches!(client_state, ClientState::InGame { .. }) {
+            self.base = None;
+            return Some(ToClient::UpdateGameState { client_state });
+        }
+        let delta = match &self.base {
+            Some(base) if self.deltas_since_snapshot < SNAPSHOT_INTERVAL => {
+                diff(base, &client_state)
+            }
+            _ => None,
+        };
+        match delta {
+            Some(delta) if delta == InGameDelta::default() => None,
+            Some(delta) => {
+                self.seq += 1;
+                self.deltas_since_snapshot += 1;
+                self.base = Some(client_state);
+                Some(ToClient::GameDelta {
+                    seq: self.seq,
+                    delta,
+                })
+            }
+            None => {
+                self.seq += 1;
+                self.deltas_since_snapshot = 0;
+                self.base = Some(client_state.clone());
+                Some(ToClient::GameSnapshot {
+                    seq: self.seq,
+                    client_state,
+                })
+            }
+        }
+    }
+}
+
+// None unless both are the same level with the same items, only their
+// states may differ
+fn diff(old: &ClientState, new: &ClientState) -> Option<InGameDelta> {
+    match (old, new) {
+        (
+            ClientState::InGame {
+                current_instruction: old_instruction,
+                ui_items: old_items,
+                instructions_executed: old_executed,
+                instructions_missed: old_missed,
+                level: old_level,
+                level_count: old_level_count,
+                level_progress: old_progress,
+                level_target: old_target,
+            },
+            ClientState::InGame {
+                current_instruction,
+                ui_items,
+                instructions_executed,
+                instructions_missed,
+                level,
+                level_count,
+                level_progress,
+                level_target,
+            },
+        ) => {
+            if old_level != level || old_level_count != level_count {
+                return None;
+            }
+            if old_items.len() != ui_items.len() {
+                return None;
+            }
+            let mut item_states = Vec::new();
+            for (old_item, item) in old_items.iter().zip(ui_items) {
+                let same_item = old_item.id == item.id
+                    && old_item.label == item.label
+                    && old_item.control_type == item.control_type
+                    && old_item.max_value == item.max_value;
+                if !same_item {
+                    return None;
+                }
+                if old_item.state != item.state {
+                    item_states.push(ItemState {
+                        id: item.id,
+                        state: item.state,
+                    });
+                }
+            }
+            Some(InGameDelta {
+                current_instruction: changed(old_instruction, current_instruction),
+                item_states,
+                instructions_executed: changed(old_executed, instructions_executed),
+                instructions_missed: changed(old_missed, instructions_missed),
+                level_progress: changed(old_progress, level_progress),
+                level_target: changed(old_target, level_target),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+    if old == new {
+        None
+    } else {
+        Some(new.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{ClientUiItem, ControlType};
+
+    fn in_game(level: usize, states: &[u8], instructions_executed: usize) -> ClientState {
+        ClientState::InGame {
+            current_instruction: "Set Flux to 1".to_string(),
+            ui_items: states
+                .iter()
+                .enumerate()
+                .map(|(id, state)| ClientUiItem {
+                    id,
+                    label: format!("Item {}", id),
+                    state: *state,
+                    control_type: ControlType::Dial,
+                    max_value: 3,
+                })
+                .collect(),
+            instructions_executed,
+            instructions_missed: 0,
+            level,
+            level_count: 3,
+            level_progress: instructions_executed,
+            level_target: 10,
+        }
+    }
+
+    fn update(client_state: ClientState) -> ToClient {
+        ToClient::UpdateGameState { client_state }
+    }
+
+    #[test]
+    fn diff_has_only_what_changed() {
+        let delta = diff(&in_game(1, &[0, 1, 2], 4), &in_game(1, &[0, 2, 2], 5)).unwrap();
+        assert_eq!(
+            delta,
+            InGameDelta {
+                item_states: vec![ItemState { id: 1, state: 2 }],
+                instructions_executed: Some(5),
+                level_progress: Some(5),
+                ..InGameDelta::default()
+            }
+        );
+    }
+
+    #[test]
+    fn diff_is_none_across_levels_or_items() {
+        assert_eq!(diff(&in_game(1, &[0, 1], 4), &in_game(2, &[0, 1], 4)), None);
+        assert_eq!(
+            diff(&in_game(1, &[0, 1], 4), &in_game(1, &[0, 1, 2], 4)),
+            None
+        );
+    }
+
+    #[test]
+    fn starts_with_a_snapshot_then_sends_deltas() {
+        let mut tracker = DeltaTracker::default();
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[0, 0], 0))),
+            Some(ToClient::GameSnapshot { seq: 1, .. })
+        ));
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[1, 0], 0))),
+            Some(ToClient::GameDelta { seq: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn empty_delta_is_suppressed() {
+        let mut tracker = DeltaTracker::default();
+        tracker.track(update(in_game(1, &[0, 0], 0)));
+        assert_eq!(tracker.track(update(in_game(1, &[0, 0], 0))), None);
+        // nothing was sent, so the sequence carries on without a gap
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[0, 1], 0))),
+            Some(ToClient::GameDelta { seq: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn level_change_forces_a_snapshot() {
+        let mut tracker = DeltaTracker::default();
+        tracker.track(update(in_game(1, &[0, 0], 0)));
+        tracker.track(update(in_game(1, &[1, 0], 0)));
+        assert!(matches!(
+            tracker.track(update(in_game(2, &[1, 0], 0))),
+            Some(ToClient::GameSnapshot { seq: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn snapshot_every_interval() {
+        let mut tracker = DeltaTracker::default();
+        tracker.track(update(in_game(1, &[0], 0)));
+        for executed in 1..=SNAPSHOT_INTERVAL as usize {
+            assert!(matches!(
+                tracker.track(update(in_game(1, &[0], executed))),
+                Some(ToClient::GameDelta { .. })
+            ));
+        }
+        let after_interval = SNAPSHOT_INTERVAL as usize + 1;
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[0], after_interval))),
+            Some(ToClient::GameSnapshot { .. })
+        ));
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[0], after_interval + 1))),
+            Some(ToClient::GameDelta { .. })
+        ));
+    }
+
+    #[test]
+    fn leaving_the_level_drops_the_base() {
+        let mut tracker = DeltaTracker::default();
+        tracker.track(update(in_game(1, &[0], 0)));
+        let finished = ClientState::Finished {
+            levels_completed: 1,
+            instructions_executed: 0,
+            instructions_missed: 0,
+        };
+        assert_eq!(
+            tracker.track(update(finished.clone())),
+            Some(update(finished))
+        );
+        assert!(matches!(
+            tracker.track(update(in_game(1, &[0], 0))),
+            Some(ToClient::GameSnapshot { .. })
+        ));
+    }
+}
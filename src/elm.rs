@@ -0,0 +1,615 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Tracer, TracerConfig, VariantFormat,
+};
+
+use crate::{
+    app::{ClientState, ControlType, Difficulty, ToBackend, ToClient},
+    backend_messages::ToBackendEnvelope,
+    chat::{ChatBody, Emote},
+    env::ToClientEnvelope,
+    protocol::PROTOCOL_VERSION,
+    user::Role,
+    Login, LoginResponse,
+};
+
+pub const API_ELM_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/client/src/Api.elm");
+
+// `server1 elm-api` rewrites client/src/Api.elm from the Rust types,
+// `server1 elm-api --check` only tells whether it's up to date
+pub fn run(check: bool) -> Result<(), String> {
+    let api = generate()?;
+    let current = std::fs::read_to_string(API_ELM_PATH).unwrap_or_default();
+    if check {
+        if current != api {
+            return Err(format!(
+                "{} is stale, run `cargo run -- elm-api`",
+                API_ELM_PATH
+            ));
+        }
+    } else if current != api {
+        std::fs::write(API_ELM_PATH, api)
+            .map_err(|e| format!("Can't write {}: {}", API_ELM_PATH, e))?;
+    }
+    Ok(())
+}
+
+// Everything the client sends or receives, traced through serde so the Elm
+// side decodes exactly what serde_json produces
+pub fn generate() -> Result<String, String> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    trace::<ToBackendEnvelope>(&mut tracer)?;
+    trace::<ToClientEnvelope>(&mut tracer)?;
+    trace::<Login>(&mut tracer)?;
+    trace::<LoginResponse>(&mut tracer)?;
+    // the tracer only explores every variant of the enums it's given,
+    // it reports the nested ones missing here
+    trace::<ToBackend>(&mut tracer)?;
+    trace::<ToClient>(&mut tracer)?;
+    trace::<ClientState>(&mut tracer)?;
+    trace::<ControlType>(&mut tracer)?;
+    trace::<Difficulty>(&mut tracer)?;
+    trace::<ChatBody>(&mut tracer)?;
+    trace::<Emote>(&mut tracer)?;
+    trace::<Role>(&mut tracer)?;
+    let registry = tracer
+        .registry()
+        .map_err(|e| format!("Incomplete trace: {:?}", e))?;
+    Generator::new(&registry).module()
+}
+
+fn trace<'de, T: serde::Deserialize<'de>>(tracer: &mut Tracer) -> Result<(), String> {
+    tracer
+        .trace_simple_type::<T>()
+        .map(|_| ())
+        .map_err(|e| format!("Can't trace {}: {:?}", std::any::type_name::<T>(), e))
+}
+
+// How a variant is represented in Elm
+enum Payload<'a> {
+    Unit,
+    // a constructor with positional arguments, serialized as an array unless there's one
+    Args(&'a [Format]),
+    // a struct variant with one field, the field becomes the argument
+    Field(&'a Named<Format>),
+    // a struct variant with more fields gets a record of its own
+    Details(String, &'a [Named<Format>]),
+}
+
+struct Variant<'a> {
+    tag: &'a str,
+    constructor: String,
+    payload: Payload<'a>,
+}
+
+struct Generator<'a> {
+    registry: &'a Registry,
+    // variant constructors that collide with another one or a record alias,
+    // they are prefixed with their type's name
+    clashing: HashSet<String>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(registry: &'a Registry) -> Self {
+        let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+        for (name, container) in registry {
+            match container {
+                ContainerFormat::Struct(_) => *seen.entry(name.clone()).or_default() += 1,
+                ContainerFormat::Enum(variants) => {
+                    for variant in variants.values() {
+                        *seen.entry(variant.name.clone()).or_default() += 1;
+                        if let VariantFormat::Struct(fields) = &variant.value {
+                            if fields.len() > 1 {
+                                *seen.entry(format!("{}Details", variant.name)).or_default() += 1;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let clashing = seen
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(name, _)| name)
+            .collect();
+        Generator { registry, clashing }
+    }
+
+    fn module(&self) -> Result<String, String> {
+        let mut declarations = vec![
+            format!(
+                "protocolVersion : Int\nprotocolVersion =\n    {}",
+                PROTOCOL_VERSION
+            ),
+            "andMap : Decoder a -> Decoder (a -> b) -> Decoder b\nandMap =\n    Decode.map2 (|>)"
+                .to_string(),
+            "encodeMaybe : (a -> Value) -> Maybe a -> Value\nencodeMaybe encode =\n    Maybe.withDefault Encode.null << Maybe.map encode"
+                .to_string(),
+        ];
+        for (name, container) in self.registry {
+            match container {
+                ContainerFormat::Struct(fields) => {
+                    declarations.append(&mut self.record(name, fields)?);
+                }
+                ContainerFormat::Enum(variants) => {
+                    declarations.append(&mut self.union(name, variants)?);
+                }
+                ContainerFormat::NewTypeStruct(format) => {
+                    declarations.push(format!(
+                        "type alias {} =\n    {}",
+                        name,
+                        self.elm_type(format)?
+                    ));
+                    declarations.push(format!(
+                        "{} : Decoder {}\n{} =\n    {}",
+                        decoder_name(name),
+                        name,
+                        decoder_name(name),
+                        self.decoder(format)?
+                    ));
+                    declarations.push(format!(
+                        "{} : {} -> Value\n{} =\n    {}",
+                        encoder_name(name),
+                        name,
+                        encoder_name(name),
+                        self.encoder(format)?
+                    ));
+                }
+                _ => return Err(format!("{} has no Elm representation", name)),
+            }
+        }
+        Ok(format!(
+            "module Api exposing (..)\n\n\
+             -- Generated from the Rust types by `cargo run -- elm-api`, don't edit by hand.\n\n\
+             import Json.Decode as Decode exposing (Decoder)\n\
+             import Json.Encode as Encode exposing (Value)\n\n\n\
+             {}\n",
+            declarations.join("\n\n\n")
+        ))
+    }
+
+    fn record(&self, name: &str, fields: &[Named<Format>]) -> Result<Vec<String>, String> {
+        let mut type_fields = Vec::new();
+        let mut decoders = Vec::new();
+        let mut encoders = Vec::new();
+        for field in fields {
+            type_fields.push(format!(
+                "{} : {}",
+                field_name(&field.name),
+                self.elm_type(&field.value)?
+            ));
+            decoders.push(format!(
+                "        |> andMap (Decode.field \"{}\" {})",
+                field.name,
+                self.decoder(&field.value)?
+            ));
+            encoders.push(format!(
+                "( \"{}\", {} {}.{} )",
+                field.name,
+                self.encoder(&field.value)?,
+                lower_first(name),
+                field_name(&field.name)
+            ));
+        }
+        let type_alias = if type_fields.is_empty() {
+            format!("type alias {} =\n    {{}}", name)
+        } else {
+            format!(
+                "type alias {} =\n    {{ {}\n    }}",
+                name,
+                type_fields.join("\n    , ")
+            )
+        };
+        let decoder = format!(
+            "{} : Decoder {}\n{} =\n    Decode.succeed {}\n{}",
+            decoder_name(name),
+            name,
+            decoder_name(name),
+            name,
+            decoders.join("\n")
+        );
+        let encoder = format!(
+            "{} : {} -> Value\n{} {} =\n    Encode.object\n        {}",
+            encoder_name(name),
+            name,
+            encoder_name(name),
+            lower_first(name),
+            list(&encoders, 8)
+        );
+        Ok(vec![type_alias, decoder, encoder])
+    }
+
+    fn union(
+        &self,
+        name: &str,
+        variants: &'a BTreeMap<u32, Named<VariantFormat>>,
+    ) -> Result<Vec<String>, String> {
+        let variants = variants
+            .values()
+            .map(|variant| self.variant(name, variant))
+            .collect::<Result<Vec<Variant>, String>>()?;
+        let mut declarations = Vec::new();
+
+        let mut constructors = Vec::new();
+        for variant in &variants {
+            let arguments = match &variant.payload {
+                Payload::Unit => vec![],
+                Payload::Args(formats) => formats
+                    .iter()
+                    .map(|format| self.elm_type(format).map(|t| parenthesize(&t)))
+                    .collect::<Result<Vec<String>, String>>()?,
+                Payload::Field(field) => vec![parenthesize(&self.elm_type(&field.value)?)],
+                Payload::Details(details, _) => vec![details.clone()],
+            };
+            constructors.push(
+                std::iter::once(variant.constructor.clone())
+                    .chain(arguments)
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            );
+        }
+        declarations.push(format!(
+            "type {}\n    = {}",
+            name,
+            constructors.join("\n    | ")
+        ));
+        for variant in &variants {
+            if let Payload::Details(details, fields) = &variant.payload {
+                declarations.append(&mut self.record(details, fields)?);
+            }
+        }
+
+        let mut decoders = Vec::new();
+        let unit_cases: Vec<String> = variants
+            .iter()
+            .filter(|variant| matches!(variant.payload, Payload::Unit))
+            .map(|variant| {
+                format!(
+                    "\"{}\" ->\n                    Decode.succeed {}",
+                    variant.tag, variant.constructor
+                )
+            })
+            .collect();
+        if !unit_cases.is_empty() {
+            decoders.push(format!(
+                "Decode.string\n    |> Decode.andThen\n        (\\tag ->\n            case tag of\n                {}\n\n                _ ->\n                    Decode.fail (\"Unknown {}: \" ++ tag)\n        )",
+                unit_cases.join("\n\n                "),
+                name
+            ));
+        }
+        for variant in &variants {
+            let payload = match &variant.payload {
+                Payload::Unit => continue,
+                Payload::Args([]) => format!("(Decode.succeed {})", variant.constructor),
+                Payload::Args([format]) => {
+                    format!(
+                        "(Decode.map {} {})",
+                        variant.constructor,
+                        self.decoder(format)?
+                    )
+                }
+                Payload::Args(formats) => {
+                    let mut decoder = format!("(Decode.succeed {}", variant.constructor);
+                    for (index, format) in formats.iter().enumerate() {
+                        decoder.push_str(&format!(
+                            " |> andMap (Decode.index {} {})",
+                            index,
+                            self.decoder(format)?
+                        ));
+                    }
+                    decoder + ")"
+                }
+                Payload::Field(field) => format!(
+                    "(Decode.map {} (Decode.field \"{}\" {}))",
+                    variant.constructor,
+                    field.name,
+                    self.decoder(&field.value)?
+                ),
+                Payload::Details(details, _) => format!(
+                    "(Decode.map {} {})",
+                    variant.constructor,
+                    decoder_name(details)
+                ),
+            };
+            decoders.push(format!("Decode.field \"{}\" {}", variant.tag, payload));
+        }
+        let decoder = match decoders.as_slice() {
+            [decoder] => indent(decoder, 4),
+            _ => {
+                let decoders: Vec<String> =
+                    decoders.iter().map(|decoder| indent(decoder, 8)).collect();
+                format!("Decode.oneOf\n        {}", list(&decoders, 8))
+            }
+        };
+        declarations.push(format!(
+            "{} : Decoder {}\n{} =\n    {}",
+            decoder_name(name),
+            name,
+            decoder_name(name),
+            decoder
+        ));
+
+        let mut cases = Vec::new();
+        for variant in &variants {
+            let (pattern, value) = match &variant.payload {
+                Payload::Unit => (
+                    variant.constructor.clone(),
+                    format!("Encode.string \"{}\"", variant.tag),
+                ),
+                Payload::Args([format]) => (
+                    format!("{} value", variant.constructor),
+                    format!("{} value", self.encoder(format)?),
+                ),
+                Payload::Args(formats) => {
+                    let arguments: Vec<String> = (0..formats.len())
+                        .map(|index| format!("a{}", index))
+                        .collect();
+                    let values = formats
+                        .iter()
+                        .zip(&arguments)
+                        .map(|(format, argument)| {
+                            self.encoder(format)
+                                .map(|encoder| format!("{} {}", encoder, argument))
+                        })
+                        .collect::<Result<Vec<String>, String>>()?;
+                    (
+                        std::iter::once(variant.constructor.clone())
+                            .chain(arguments)
+                            .collect::<Vec<String>>()
+                            .join(" "),
+                        format!("Encode.list identity {}", inline_list(&values)),
+                    )
+                }
+                Payload::Field(field) => (
+                    format!("{} value", variant.constructor),
+                    format!(
+                        "Encode.object [ ( \"{}\", {} value ) ]",
+                        field.name,
+                        self.encoder(&field.value)?
+                    ),
+                ),
+                Payload::Details(details, _) => (
+                    format!("{} details", variant.constructor),
+                    format!("{} details", encoder_name(details)),
+                ),
+            };
+            let value = match variant.payload {
+                Payload::Unit => value,
+                _ => format!("Encode.object [ ( \"{}\", {} ) ]", variant.tag, value),
+            };
+            cases.push(format!("{} ->\n            {}", pattern, value));
+        }
+        declarations.push(format!(
+            "{} : {} -> Value\n{} {} =\n    case {} of\n        {}",
+            encoder_name(name),
+            name,
+            encoder_name(name),
+            lower_first(name),
+            lower_first(name),
+            cases.join("\n\n        ")
+        ));
+
+        // fieldless enums, e.g. for showing them
+        if variants
+            .iter()
+            .all(|variant| matches!(variant.payload, Payload::Unit))
+        {
+            let cases: Vec<String> = variants
+                .iter()
+                .map(|variant| {
+                    format!(
+                        "{} ->\n            \"{}\"",
+                        variant.constructor, variant.tag
+                    )
+                })
+                .collect();
+            declarations.push(format!(
+                "{}ToString : {} -> String\n{}ToString {} =\n    case {} of\n        {}",
+                lower_first(name),
+                name,
+                lower_first(name),
+                lower_first(name),
+                lower_first(name),
+                cases.join("\n\n        ")
+            ));
+        }
+        Ok(declarations)
+    }
+
+    fn variant(
+        &self,
+        name: &str,
+        variant: &'a Named<VariantFormat>,
+    ) -> Result<Variant<'a>, String> {
+        let constructor = if self.clashing.contains(&variant.name) {
+            format!("{}{}", name, variant.name)
+        } else {
+            variant.name.clone()
+        };
+        let payload = match &variant.value {
+            VariantFormat::Unit => Payload::Unit,
+            VariantFormat::NewType(format) => Payload::Args(std::slice::from_ref(format.as_ref())),
+            VariantFormat::Tuple(formats) => Payload::Args(formats),
+            VariantFormat::Struct(fields) if fields.len() == 1 => Payload::Field(&fields[0]),
+            VariantFormat::Struct(fields) => {
+                let details = format!("{}Details", variant.name);
+                let details = if self.clashing.contains(&details) {
+                    format!("{}{}", name, details)
+                } else {
+                    details
+                };
+                Payload::Details(details, fields)
+            }
+            VariantFormat::Variable(_) => {
+                return Err(format!("{}::{} wasn't fully traced", name, variant.name))
+            }
+        };
+        Ok(Variant {
+            tag: &variant.name,
+            constructor,
+            payload,
+        })
+    }
+
+    fn elm_type(&self, format: &Format) -> Result<String, String> {
+        Ok(match format {
+            Format::TypeName(name) => name.clone(),
+            Format::Unit => "()".to_string(),
+            Format::Bool => "Bool".to_string(),
+            Format::I8
+            | Format::I16
+            | Format::I32
+            | Format::I64
+            | Format::U8
+            | Format::U16
+            | Format::U32
+            | Format::U64 => "Int".to_string(),
+            Format::F32 | Format::F64 => "Float".to_string(),
+            Format::Char | Format::Str => "String".to_string(),
+            Format::Option(format) => format!("Maybe {}", parenthesize(&self.elm_type(format)?)),
+            Format::Seq(format) => format!("List {}", parenthesize(&self.elm_type(format)?)),
+            _ => return Err(format!("{:?} has no Elm representation", format)),
+        })
+    }
+
+    fn decoder(&self, format: &Format) -> Result<String, String> {
+        Ok(match format {
+            Format::TypeName(name) => decoder_name(name),
+            Format::Unit => "(Decode.null ())".to_string(),
+            Format::Bool => "Decode.bool".to_string(),
+            Format::I8
+            | Format::I16
+            | Format::I32
+            | Format::I64
+            | Format::U8
+            | Format::U16
+            | Format::U32
+            | Format::U64 => "Decode.int".to_string(),
+            Format::F32 | Format::F64 => "Decode.float".to_string(),
+            Format::Char | Format::Str => "Decode.string".to_string(),
+            Format::Option(format) => format!("(Decode.nullable {})", self.decoder(format)?),
+            Format::Seq(format) => format!("(Decode.list {})", self.decoder(format)?),
+            _ => return Err(format!("{:?} has no Elm representation", format)),
+        })
+    }
+
+    fn encoder(&self, format: &Format) -> Result<String, String> {
+        Ok(match format {
+            Format::TypeName(name) => encoder_name(name),
+            Format::Unit => "(\\_ -> Encode.null)".to_string(),
+            Format::Bool => "Encode.bool".to_string(),
+            Format::I8
+            | Format::I16
+            | Format::I32
+            | Format::I64
+            | Format::U8
+            | Format::U16
+            | Format::U32
+            | Format::U64 => "Encode.int".to_string(),
+            Format::F32 | Format::F64 => "Encode.float".to_string(),
+            Format::Char | Format::Str => "Encode.string".to_string(),
+            Format::Option(format) => {
+                format!("encodeMaybe {}", parenthesize(&self.encoder(format)?))
+            }
+            Format::Seq(format) => format!("Encode.list {}", parenthesize(&self.encoder(format)?)),
+            _ => return Err(format!("{:?} has no Elm representation", format)),
+        })
+    }
+}
+
+fn decoder_name(name: &str) -> String {
+    format!("{}Decoder", lower_first(name))
+}
+
+fn encoder_name(name: &str) -> String {
+    format!("encode{}", name)
+}
+
+fn lower_first(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// snake_case to camelCase, keywords get a trailing underscore
+fn field_name(name: &str) -> String {
+    let mut camel = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    const KEYWORDS: [&str; 13] = [
+        "if", "then", "else", "case", "of", "let", "in", "type", "module", "where", "import",
+        "exposing", "port",
+    ];
+    if KEYWORDS.contains(&camel.as_str()) {
+        camel.push('_');
+    }
+    camel
+}
+
+fn parenthesize(expression: &str) -> String {
+    if expression.contains(' ') && !expression.starts_with('(') {
+        format!("({})", expression)
+    } else {
+        expression.to_string()
+    }
+}
+
+// every line but the first moves right by `by`
+fn indent(text: &str, by: usize) -> String {
+    let by = " ".repeat(by);
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            if index == 0 || line.is_empty() {
+                line.to_string()
+            } else {
+                format!("{}{}", by, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// one item per line, the way elm-format lays out longer lists
+fn list(items: &[String], indent: usize) -> String {
+    if items.is_empty() {
+        return "[]".to_string();
+    }
+    let indent = " ".repeat(indent);
+    format!("[ {}\n{}]", items.join(&format!("\n{}, ", indent)), indent)
+}
+
+fn inline_list(items: &[String]) -> String {
+    if items.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[ {} ]", items.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_elm_is_up_to_date() {
+        let current = std::fs::read_to_string(API_ELM_PATH).unwrap();
+        assert!(
+            current == generate().unwrap(),
+            "client/src/Api.elm is stale, run `cargo run -- elm-api`"
+        );
+    }
+}
@@ -1,50 +1,93 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use tokio::sync::{
+    mpsc::{UnboundedReceiver, UnboundedSender},
+    RwLock,
+};
 
 use crate::{
     app::{ClientMessage, RocketJamApp, ToClient},
-    user::UserServiceImpl,
+    delta::DeltaTracker,
+    health::HealthServiceImpl,
+    metrics::Metrics,
+    protocol,
+    rounds::RoundServiceImpl,
+    stats::StatsServiceImpl,
+    user::{UserId, UserServiceImpl},
 };
 
-use log::warn;
+use tracing::warn;
 
 #[derive(Clone)]
 pub struct Env {
     pub client_broadcaster: ClientBroadcaster,
     pub app: RocketJamApp,
     pub user_service: UserServiceImpl,
+    pub round_service: RoundServiceImpl,
+    pub stats_service: StatsServiceImpl,
+    pub health_service: HealthServiceImpl,
+    pub metrics: Metrics,
 }
 
 #[derive(Debug, Clone)]
 pub struct Client {
     pub token: String,
     pub user_id: i32,
-    pub sender: Option<UnboundedSender<ToClientEnvelope>>,
+    // sent along with the login, see protocol.rs
+    pub protocol_version: u32,
+    pub sender: Option<UnboundedSender<OutgoingEvent>>,
+}
+
+// What goes down a client's event stream, `request_id` names the request
+// that caused it, if any
+#[derive(Debug, Clone)]
+pub struct OutgoingEvent {
+    pub envelope: ToClientEnvelope,
+    pub request_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ToClientEnvelope {
     SuperSeeded(),
     AppMsg(ToClient),
+    // the client is behind, it's still served if its version isn't below `min_version`
+    UpgradeRequired {
+        server_version: u32,
+        min_version: u32,
+    },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Session {
+    // the start of the token, enough to tell sessions apart
+    pub token_prefix: String,
+    pub user_id: UserId,
+    pub protocol_version: u32,
+    pub connected: bool,
 }
 
 #[derive(Clone)]
 pub struct ClientBroadcaster {
     clients_by_token: std::sync::Arc<RwLock<HashMap<String, Client>>>,
+    // what each event stream was sent last, by token
+    delta_trackers: Arc<RwLock<HashMap<String, DeltaTracker>>>,
 }
 
 impl ClientBroadcaster {
     pub fn new() -> Self {
         ClientBroadcaster {
             clients_by_token: Arc::new(RwLock::new(HashMap::new())),
+            delta_trackers: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
     pub async fn get(&self, token: &String) -> Option<Client> {
         let map = self.clients_by_token.read().await;
-        map.get(token).map(|client| client.clone())
+        map.get(token).cloned()
     }
 
     pub async fn update_client(&self, token: String, client: Client) {
@@ -52,22 +95,179 @@ impl ClientBroadcaster {
         registry.insert(token, client);
     }
 
-    pub async fn send_to_user(&self, (user_id, to_client): ClientMessage) {
+    // Attaches a new event stream to the logged in `client`, the one it had
+    // before is told it was superseded
+    pub async fn connect(&self, client: Client) -> UnboundedReceiver<OutgoingEvent> {
+        // logout previously registered client
+        if let Some(sender) = &client.sender {
+            let super_seeded = OutgoingEvent {
+                envelope: ToClientEnvelope::SuperSeeded(),
+                request_id: None,
+            };
+            if let Err(some_error) = sender.send(super_seeded) {
+                warn!(
+                    "Can't send SuperSeed but it doesn't matter really {:?}",
+                    some_error
+                );
+            }
+        }
+        //
+        // Use an unbounded channel to handle buffering and flushing of messages
+        // to the event source...
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // still served, but it should reload when it gets the chance
+        if client.protocol_version < protocol::PROTOCOL_VERSION {
+            let upgrade_required = OutgoingEvent {
+                envelope: ToClientEnvelope::UpgradeRequired {
+                    server_version: protocol::PROTOCOL_VERSION,
+                    min_version: protocol::MIN_PROTOCOL_VERSION,
+                },
+                request_id: None,
+            };
+            if let Err(e) = tx.send(upgrade_required) {
+                warn!("Cannot send {:?}", e);
+            }
+        }
+
+        let token = client.token.clone();
+        let updated_client = Client {
+            sender: Some(tx),
+            ..client
+        };
+        // the new stream starts over with a full snapshot. Both under the lock
+        // `send` holds, or it could rebuild the tracker for the old stream
+        // in between and the new one would start with a delta.
+        let mut clients_by_token = self.clients_by_token.write().await;
+        self.delta_trackers.write().await.remove(&token);
+        clients_by_token.insert(token, updated_client);
+        rx
+    }
+
+    // The client lost track of the deltas, it's sent the next state in full
+    pub async fn resync(&self, token: &str) {
+        self.delta_trackers.write().await.remove(token);
+    }
+
+    pub async fn sessions(&self) -> Vec<Session> {
+        let clients_by_token = self.clients_by_token.read().await;
+        let mut sessions: Vec<Session> = clients_by_token
+            .values()
+            .map(|c| Session {
+                token_prefix: c.token.chars().take(8).collect(),
+                user_id: c.user_id,
+                protocol_version: c.protocol_version,
+                connected: matches!(&c.sender, Some(sender) if !sender.is_closed()),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.user_id);
+        sessions
+    }
+
+    // Logs the user out everywhere, their tokens stop working. Returns how many there were.
+    pub async fn revoke_user(&self, user_id: UserId) -> usize {
+        let mut clients_by_token = self.clients_by_token.write().await;
+        let tokens: Vec<String> = clients_by_token
+            .values()
+            .filter(|c| c.user_id == user_id)
+            .map(|c| c.token.clone())
+            .collect();
+        let mut delta_trackers = self.delta_trackers.write().await;
+        for token in &tokens {
+            delta_trackers.remove(token);
+            if let Some(Client {
+                sender: Some(sender),
+                ..
+            }) = clients_by_token.remove(token)
+            {
+                let super_seeded = OutgoingEvent {
+                    envelope: ToClientEnvelope::SuperSeeded(),
+                    request_id: None,
+                };
+                if let Err(e) = sender.send(super_seeded) {
+                    warn!("Cannot send {:?}", e);
+                }
+            }
+        }
+        tokens.len()
+    }
+
+    pub async fn broadcast(&self, to_client: ToClient) {
+        let clients_by_token = self.clients_by_token.read().await;
+        for client in clients_by_token.values() {
+            if let (Some(sender), Some(to_client)) = (
+                &client.sender,
+                protocol::downgrade(&to_client, client.protocol_version),
+            ) {
+                let outgoing = OutgoingEvent {
+                    envelope: ToClientEnvelope::AppMsg(to_client),
+                    request_id: None,
+                };
+                // closed streams are expected here, their clients just went away
+                let _ = sender.send(outgoing);
+            }
+        }
+    }
+
+    pub async fn connected_user_ids(&self) -> HashSet<UserId> {
+        let clients_by_token = self.clients_by_token.read().await;
+        clients_by_token
+            .values()
+            .filter(|c| matches!(&c.sender, Some(sender) if !sender.is_closed()))
+            .map(|c| c.user_id)
+            .collect()
+    }
+
+    // clients whose event stream is still open
+    pub async fn connection_count(&self) -> usize {
+        let clients_by_token = self.clients_by_token.read().await;
+        clients_by_token
+            .values()
+            .filter(|c| matches!(&c.sender, Some(sender) if !sender.is_closed()))
+            .count()
+    }
+
+    pub async fn send_to_user(&self, client_message: ClientMessage) {
+        self.send(client_message, None).await;
+    }
+
+    pub async fn send_to_user_for_request(&self, client_message: ClientMessage, request_id: &str) {
+        self.send(client_message, Some(request_id.to_string()))
+            .await;
+    }
+
+    async fn send(&self, (user_id, to_client): ClientMessage, request_id: Option<String>) {
         let clients_by_token = self.clients_by_token.read().await;
         let senders_for_user = clients_by_token
             .values()
             .filter(|c| c.user_id == user_id)
-            .map(|c| match &c.sender {
-                Some(sender) => Some((&c.token, sender)),
-                None => None,
-            })
-            .flatten();
+            .filter_map(|c| c.sender.as_ref().map(|sender| (c, sender)));
         if senders_for_user.clone().count() == 0 {
             warn!("No clients for user {:?} to send response to", &user_id);
         }
 
-        senders_for_user.for_each(|(_, sender)| {
-            let send_result = sender.send(ToClientEnvelope::AppMsg(to_client.clone()));
+        let mut delta_trackers = self.delta_trackers.write().await;
+        senders_for_user.for_each(|(client, sender)| {
+            let to_client = match protocol::downgrade(&to_client, client.protocol_version) {
+                Some(to_client) => to_client,
+                None => return,
+            };
+            let to_client = if protocol::supports_deltas(client.protocol_version) {
+                match delta_trackers
+                    .entry(client.token.clone())
+                    .or_default()
+                    .track(to_client)
+                {
+                    Some(to_client) => to_client,
+                    None => return,
+                }
+            } else {
+                to_client
+            };
+            let send_result = sender.send(OutgoingEvent {
+                envelope: ToClientEnvelope::AppMsg(to_client),
+                request_id: request_id.clone(),
+            });
             if let Err(e) = send_result {
                 warn!("Cannot send {:?}", e);
             }
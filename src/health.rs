@@ -0,0 +1,90 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum TaskStatus {
+    Running,
+    Stopped,
+    Panicked(String),
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct HealthReport {
+    pub ok: bool,
+    pub checks: BTreeMap<&'static str, String>,
+}
+
+// Keeps track of the background tasks and checks the database for /healthz and /readyz
+#[derive(Clone)]
+pub struct HealthServiceImpl {
+    pool: PgPool,
+    tasks: Arc<RwLock<BTreeMap<&'static str, TaskStatus>>>,
+}
+
+impl HealthServiceImpl {
+    pub fn new(pool: &PgPool) -> HealthServiceImpl {
+        HealthServiceImpl {
+            pool: pool.clone(),
+            tasks: Arc::new(RwLock::new(BTreeMap::new())),
+        }
+    }
+
+    // Called by the supervisor whenever a background task starts or ends
+    pub async fn set_status(&self, name: &'static str, status: TaskStatus) {
+        self.tasks.write().await.insert(name, status);
+    }
+
+    // The process is alive and all background tasks are still running
+    pub async fn liveness(&self) -> HealthReport {
+        let tasks = self.tasks.read().await;
+        let checks: BTreeMap<&'static str, String> = tasks
+            .iter()
+            .map(|(name, status)| (*name, format!("{:?}", status)))
+            .collect();
+        HealthReport {
+            ok: tasks.values().all(|status| *status == TaskStatus::Running),
+            checks,
+        }
+    }
+
+    // Liveness plus a reachable database with all migrations applied
+    pub async fn readiness(&self) -> HealthReport {
+        let mut report = self.liveness().await;
+        match sqlx::query("SELECT 1").execute(&self.pool).await {
+            Ok(_) => {
+                report.checks.insert("database", "Ok".to_string());
+            }
+            Err(e) => {
+                report.ok = false;
+                report.checks.insert("database", format!("{}", e));
+            }
+        }
+        let applied = sqlx::query_scalar::<_, i64>(
+            "SELECT version FROM _sqlx_migrations WHERE success ORDER BY version",
+        )
+        .fetch_all(&self.pool)
+        .await;
+        match applied {
+            Ok(applied) => {
+                let pending = sqlx::migrate!()
+                    .iter()
+                    .filter(|migration| !applied.contains(&migration.version))
+                    .count();
+                if pending > 0 {
+                    report.ok = false;
+                }
+                report
+                    .checks
+                    .insert("migrations", format!("{} pending", pending));
+            }
+            Err(e) => {
+                report.ok = false;
+                report.checks.insert("migrations", format!("{}", e));
+            }
+        }
+        report
+    }
+}
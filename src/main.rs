@@ -1,16 +1,36 @@
+mod admin;
 mod app;
+mod auth;
 mod backend_messages;
+mod catalog;
+mod chat;
+mod delta;
+mod elm;
 mod env;
+mod health;
+mod metrics;
+mod protocol;
+mod rounds;
+mod socket;
+mod stats;
+mod supervisor;
 mod user;
 
-use env::{Client, ClientBroadcaster, Env, ToClientEnvelope};
+use env::{Client, ClientBroadcaster, Env, OutgoingEvent};
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPoolOptions;
 
-use std::time::Duration;
-use tokio::{sync::mpsc::Sender, time::sleep};
-use warp::{Filter, Rejection, Reply};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+    task::JoinHandle,
+    time::sleep,
+};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
 
 use backend_messages::{Processor, ToBackendEnvelope};
 
@@ -19,15 +39,32 @@ use warp::sse::Event;
 
 use uuid::Uuid;
 
-use app::RocketJamApp;
-use log::info;
-
-use crate::user::UserServiceImpl;
+use app::{ReplayQuery, RocketJamApp};
+use tracing::{error, field, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    catalog::ItemCatalog,
+    chat::BlockList,
+    health::{HealthReport, HealthServiceImpl},
+    metrics::Metrics,
+    rounds::{RoundServiceImpl, RoundWrite},
+    stats::{LeaderboardQuery, StatsServiceImpl},
+    supervisor::Supervisor,
+    user::{Role, User, UserId, UserServiceImpl},
+};
 
 #[derive(Serialize, Deserialize)]
 struct Login {
     username: String,
     password: String,
+    // missing from clients that predate versioning
+    #[serde(default = "unversioned")]
+    protocol_version: u32,
+}
+
+fn unversioned() -> u32 {
+    protocol::UNVERSIONED
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,6 +77,7 @@ enum LoginResponse {
 struct LoginSuccessDetails {
     token: String,
     username: String,
+    role: Role,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,30 +85,89 @@ struct LoginFailureDetails {
     msg: String,
 }
 
-#[derive(Serialize, Deserialize)]
-enum ActionResponse {
-    Success(String),
-    Failure(String),
+struct Gameloop {
+    env: Env,
+    persister: UnboundedSender<RoundWrite>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ConnectRequest {
-    token: String,
+impl Gameloop {
+    fn new(env: Env, persister: UnboundedSender<RoundWrite>) -> Self {
+        Gameloop { env, persister }
+    }
+    fn start_loop(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                // rounds tick every `tick_interval` of these
+                sleep(Duration::from_secs(1)).await;
+                let timer = self.env.metrics.tick_duration.start_timer();
+                let msgs = self.env.app.tick().await;
+                timer.observe_duration();
+                for client_message in msgs {
+                    self.env
+                        .client_broadcaster
+                        .send_to_user(client_message)
+                        .await;
+                }
+                // saved by the persister, the next tick doesn't wait for the database
+                let dirty_rounds = self.env.app.take_dirty_rounds().await;
+                let deleted_rounds = self.env.app.take_deleted_rounds().await;
+                let writes = dirty_rounds
+                    .into_iter()
+                    .map(RoundWrite::Save)
+                    .chain(deleted_rounds.into_iter().map(RoundWrite::Delete));
+                for write in writes {
+                    if self.persister.send(write).is_err() {
+                        error!("Can't persist rounds, the persister is gone");
+                    }
+                }
+            }
+        })
+    }
 }
 
-struct Gameloop {
+struct Persister {
     env: Env,
+    // shared, so a persister restarted after a panic picks up the queued writes
+    receiver: Arc<Mutex<UnboundedReceiver<RoundWrite>>>,
 }
 
-impl Gameloop {
+impl Persister {
+    fn new(env: Env, receiver: Arc<Mutex<UnboundedReceiver<RoundWrite>>>) -> Self {
+        Persister { env, receiver }
+    }
+    fn start_loop(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let write = match self.receiver.lock().await.recv().await {
+                    Some(write) => write,
+                    None => break,
+                };
+                match write {
+                    RoundWrite::Save(round) => self.env.round_service.save_round(&round).await,
+                    RoundWrite::Delete(round_id) => {
+                        self.env.round_service.delete_round(&round_id).await
+                    }
+                }
+            }
+        })
+    }
+}
+
+struct Matchmaker {
+    env: Env,
+}
+
+impl Matchmaker {
     fn new(env: Env) -> Self {
-        Gameloop { env }
+        Matchmaker { env }
     }
-    fn start_loop(self) {
+    fn start_loop(self) -> JoinHandle<()> {
         tokio::spawn(async move {
             loop {
-                sleep(Duration::from_secs(3)).await;
-                let msgs = self.env.app.tick().await;
+                sleep(Duration::from_secs(1)).await;
+                let connected_user_ids = self.env.client_broadcaster.connected_user_ids().await;
+                let mut msgs = self.env.app.prune_disconnected(&connected_user_ids).await;
+                msgs.append(&mut self.env.app.matchmake().await);
                 for client_message in msgs {
                     self.env
                         .client_broadcaster
@@ -78,33 +175,93 @@ impl Gameloop {
                         .await;
                 }
             }
-        });
+        })
     }
 }
 
+// LOG_FORMAT=json switches to one JSON object per line, RUST_LOG filters as before
+fn init_tracing() {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    if std::env::var("LOG_FORMAT").as_deref() == Ok("json") {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+}
+
+const DATABASE_URL: &str = "postgres://rust@localhost/rust_server";
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+fn with_request_id() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(REQUEST_ID_HEADER)
+        .map(|request_id: Option<String>| request_id.unwrap_or_else(|| Uuid::new_v4().to_string()))
+}
+
 fn with_env(env: Env) -> impl Filter<Extract = (Env,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || env.clone())
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("elm-api") {
+        if let Err(e) = elm::run(args.iter().any(|arg| arg == "--check")) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    init_tracing();
     let (sender, receiver) = tokio::sync::mpsc::channel::<ToBackendEnvelope>(32);
 
     let pool = PgPoolOptions::new()
         .max_connections(5)
-        .connect("postgres://rust@localhost/rust_server")
+        .connect(DATABASE_URL)
         .await
         .unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
 
+    let block_list = BlockList::load("blocklist.txt").unwrap_or_else(|e| {
+        warn!("{}, chat goes unfiltered", e);
+        BlockList::new(vec![])
+    });
+    let metrics = Metrics::new();
     let env = Env {
         client_broadcaster: ClientBroadcaster::new(),
-        app: RocketJamApp::new(),
-        user_service: UserServiceImpl::new(&pool),
+        app: RocketJamApp::new(
+            ItemCatalog::load("catalog.json").unwrap(),
+            Box::new(block_list),
+        ),
+        user_service: UserServiceImpl::new(&pool, &metrics),
+        round_service: RoundServiceImpl::new(&pool),
+        stats_service: StatsServiceImpl::new(&pool),
+        health_service: HealthServiceImpl::new(&pool),
+        metrics,
     };
-
-    Gameloop::new(env.clone()).start_loop();
-    Processor::new(env.clone(), receiver).start_loop();
+    let round_logs = env.round_service.find_unfinished_round_logs().await;
+    env.app.restore(round_logs).await;
+
+    let supervisor = Supervisor::new(&env.health_service, &env.metrics);
+    let (persister_sender, persister_receiver) = unbounded_channel::<RoundWrite>();
+    let persister_receiver = Arc::new(Mutex::new(persister_receiver));
+    supervisor.supervise("persister", {
+        let env = env.clone();
+        move || Persister::new(env.clone(), persister_receiver.clone()).start_loop()
+    });
+    supervisor.supervise("gameloop", {
+        let env = env.clone();
+        move || Gameloop::new(env.clone(), persister_sender.clone()).start_loop()
+    });
+    supervisor.supervise("matchmaker", {
+        let env = env.clone();
+        move || Matchmaker::new(env.clone()).start_loop()
+    });
+    let receiver = Arc::new(Mutex::new(receiver));
+    supervisor.supervise("processor", {
+        let env = env.clone();
+        move || Processor::new(env.clone(), receiver.clone()).start_loop()
+    });
 
     let static_files = warp::any().and(warp::fs::dir("client"));
 
@@ -114,8 +271,12 @@ async fn main() {
         .and(warp::body::json())
         .and_then(auth_handler);
 
+    let socket_route = socket::route(env.clone(), sender.clone());
+
     let action = warp::path("action")
         .and(warp::any().map(move || sender.clone()))
+        .and(with_env(env.clone()))
+        .and(with_request_id())
         .and(warp::body::json())
         .and_then(action_handler);
 
@@ -123,19 +284,102 @@ async fn main() {
         .and(with_env(env.clone()))
         .and_then(event_handler);
 
-    let post_routes = warp::post().and(login.or(action));
-    let get_routes = warp::get().and(event_route);
+    // for the round's players and moderators, once it's finished
+    let replay_route = warp::path!("rounds" / String / "replay")
+        .and(auth::with_role(env.clone(), Role::Player))
+        .and(warp::query::<ReplayQuery>())
+        .and(with_env(env.clone()))
+        .and_then(replay_handler)
+        .recover(auth::handle_rejection);
 
-    warp::serve(post_routes.or(static_files).or(get_routes))
-        .run(([127, 0, 0, 1], 3030))
-        .await;
+    let history_route = warp::path!("users" / i32 / "history")
+        .and(with_env(env.clone()))
+        .and_then(history_handler);
+
+    let stats_route = warp::path!("users" / i32 / "stats")
+        .and(with_env(env.clone()))
+        .and_then(stats_handler);
+
+    let leaderboard_route = warp::path!("leaderboard")
+        .and(warp::query::<LeaderboardQuery>())
+        .and(with_env(env.clone()))
+        .and_then(leaderboard_handler);
+
+    let healthz_route = warp::path!("healthz")
+        .and(with_env(env.clone()))
+        .and_then(healthz_handler);
+
+    let readyz_route = warp::path!("readyz")
+        .and(with_env(env.clone()))
+        .and_then(readyz_handler);
+
+    let metrics_route = warp::path!("metrics")
+        .and(with_env(env.clone()))
+        .and_then(metrics_handler);
+
+    let admin_routes = admin::routes(env.clone());
+
+    let post_routes = warp::post().and(login.or(action));
+    let get_routes = warp::get().and(
+        event_route
+            .or(socket_route)
+            .or(replay_route)
+            .or(history_route)
+            .or(stats_route)
+            .or(leaderboard_route)
+            .or(metrics_route)
+            .or(healthz_route)
+            .or(readyz_route),
+    );
+
+    let request_span = warp::trace(|info| {
+        info_span!(
+            "request",
+            method = %info.method(),
+            path = %info.path(),
+            request_id = field::Empty,
+        )
+    });
+
+    warp::serve(
+        admin_routes
+            .or(post_routes)
+            .or(static_files)
+            .or(get_routes)
+            .with(request_span),
+    )
+    .run(([127, 0, 0, 1], 3030))
+    .await;
 }
 
 async fn auth_handler(env: Env, login: Login) -> std::result::Result<impl Reply, Rejection> {
+    if !protocol::is_supported(login.protocol_version) {
+        warn!(
+            "Refusing login of {:?} with protocol version {:?}",
+            login.username, login.protocol_version
+        );
+        env.metrics
+            .logins
+            .with_label_values(&["unsupported_version"])
+            .inc();
+        // a newer client than the server means a rollout is under way
+        let msg = if login.protocol_version > protocol::PROTOCOL_VERSION {
+            "The server is being updated, please try again in a moment"
+        } else {
+            "This version of the game is out of date, please reload the page"
+        };
+        return Ok(warp::reply::json(&LoginResponse::Failure(
+            LoginFailureDetails {
+                msg: msg.to_string(),
+            },
+        )));
+    }
     let user = env
         .user_service
         .find_user_by_name_and_password(&login.username, &login.password)
         .await;
+    let login_result = if user.is_some() { "success" } else { "failure" };
+    env.metrics.logins.with_label_values(&[login_result]).inc();
     let login_response = match user {
         Some(user) => {
             if user.hashed_password.eq(&login.password) {
@@ -144,6 +388,7 @@ async fn auth_handler(env: Env, login: Login) -> std::result::Result<impl Reply,
                 let client = Client {
                     token: token.to_string(),
                     user_id: user.id,
+                    protocol_version: login.protocol_version,
                     sender: None,
                 };
 
@@ -154,6 +399,7 @@ async fn auth_handler(env: Env, login: Login) -> std::result::Result<impl Reply,
                 LoginResponse::Success(LoginSuccessDetails {
                     token: token.to_string(),
                     username: user.username,
+                    role: user.role,
                 })
             } else {
                 LoginResponse::Failure(LoginFailureDetails {
@@ -170,42 +416,42 @@ async fn auth_handler(env: Env, login: Login) -> std::result::Result<impl Reply,
 
 async fn action_handler(
     sender: Sender<ToBackendEnvelope>,
-    action: ToBackendEnvelope,
+    env: Env,
+    request_id: String,
+    mut action: ToBackendEnvelope,
 ) -> std::result::Result<impl Reply, Rejection> {
-    info!("Received action {:?}", action);
+    tracing::Span::current().record("request_id", &request_id.as_str());
+    info!("Received action {}", action.name());
+    action.request_id = Some(request_id.clone());
     // should probably do auth & resolution to user already here?
-    sender.send(action.clone()).await.unwrap();
-    Ok(warp::reply::json(&action))
+    // counted before sending, the processor takes it off as soon as it receives
+    env.metrics.queue_depth.inc();
+    if let Err(e) = sender.send(action.clone()).await {
+        env.metrics.queue_depth.dec();
+        error!("Can't queue action, the processor is gone: {:?}", e);
+        let reply =
+            warp::reply::with_status(warp::reply::json(&action), StatusCode::SERVICE_UNAVAILABLE);
+        return Ok(warp::reply::with_header(reply, REQUEST_ID_HEADER, request_id).into_response());
+    }
+    Ok(
+        warp::reply::with_header(warp::reply::json(&action), REQUEST_ID_HEADER, request_id)
+            .into_response(),
+    )
 }
 
 async fn event_handler(token: String, env: Env) -> std::result::Result<impl Reply, Rejection> {
     if let Some(client) = env.client_broadcaster.get(&token).await {
-        // logout previously registered client
-        if let Some(sender) = &client.sender {
-            if let Err(some_error) = sender.send(ToClientEnvelope::SuperSeeded()) {
-                println!(
-                    "Can't send SuperSeed but it doesn't matter really {:?}",
-                    some_error
-                );
-            }
-        }
-        //
-        // Use an unbounded channel to handle buffering and flushing of messages
-        // to the event source...
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-        let rx: UnboundedReceiverStream<ToClientEnvelope> = UnboundedReceiverStream::new(rx);
-
-        let updated_client = Client {
-            token: client.token.clone(),
-            user_id: client.user_id,
-            sender: Some(tx),
-        };
-        env.client_broadcaster
-            .update_client(token, updated_client)
-            .await;
-        let event_stream = rx.map(|to_client| {
-            info!("Sending event to client {:?}", to_client);
-            let r: Result<Event, warp::Error> = Ok(Event::default().json_data(to_client).unwrap());
+        let rx = env.client_broadcaster.connect(client).await;
+        let rx: UnboundedReceiverStream<OutgoingEvent> = UnboundedReceiverStream::new(rx);
+        let event_stream = rx.map(|outgoing| {
+            info!(request_id = ?outgoing.request_id, "Sending event to client {:?}", outgoing.envelope);
+            let event = Event::default().json_data(outgoing.envelope).unwrap();
+            // the SSE id ties the event to the request that caused it
+            let event = match outgoing.request_id {
+                Some(request_id) => event.id(request_id),
+                None => event,
+            };
+            let r: Result<Event, warp::Error> = Ok(event);
             r
         });
         Ok(warp::sse::reply(event_stream))
@@ -213,3 +459,77 @@ async fn event_handler(token: String, env: Env) -> std::result::Result<impl Repl
         Err(warp::reject::not_found())
     }
 }
+
+async fn replay_handler(
+    round_id: String,
+    user: User,
+    query: ReplayQuery,
+    env: Env,
+) -> std::result::Result<warp::reply::Response, Rejection> {
+    // the database lags behind rounds still in memory
+    let events = match env.app.round_log(&round_id).await {
+        Some(events) => Some(events),
+        None => env.round_service.find_round_log(&round_id).await,
+    };
+    let events = match events {
+        Some(events) => events,
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    if user.role < Role::Moderator && !app::participants(&events).contains(&user.id) {
+        return Ok(StatusCode::FORBIDDEN.into_response());
+    }
+    match env.app.replay(&round_id, &events, &query).await {
+        Some(replay) if replay.finished => Ok(warp::reply::json(&replay).into_response()),
+        // it would give away the hidden state of a game still being played
+        Some(_) => Ok(StatusCode::CONFLICT.into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+async fn history_handler(user_id: UserId, env: Env) -> std::result::Result<impl Reply, Rejection> {
+    let history = env.round_service.find_history_for_user(user_id).await;
+    Ok(warp::reply::json(&history))
+}
+
+async fn stats_handler(user_id: UserId, env: Env) -> std::result::Result<impl Reply, Rejection> {
+    match env.stats_service.find_user_stats(user_id).await {
+        Some(stats) => Ok(warp::reply::json(&stats)),
+        None => Err(warp::reject::not_found()),
+    }
+}
+
+async fn healthz_handler(env: Env) -> std::result::Result<impl Reply, Rejection> {
+    Ok(health_reply(env.health_service.liveness().await))
+}
+
+async fn readyz_handler(env: Env) -> std::result::Result<impl Reply, Rejection> {
+    Ok(health_reply(env.health_service.readiness().await))
+}
+
+fn health_reply(report: HealthReport) -> impl Reply {
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    warp::reply::with_status(warp::reply::json(&report), status)
+}
+
+async fn metrics_handler(env: Env) -> std::result::Result<impl Reply, Rejection> {
+    let metrics = &env.metrics;
+    metrics
+        .sse_connections
+        .set(env.client_broadcaster.connection_count().await as i64);
+    for (state, count) in env.app.round_counts().await {
+        metrics.rounds.with_label_values(&[state]).set(count as i64);
+    }
+    Ok(metrics.render())
+}
+
+async fn leaderboard_handler(
+    query: LeaderboardQuery,
+    env: Env,
+) -> std::result::Result<impl Reply, Rejection> {
+    let leaderboard = env.stats_service.find_leaderboard(query.period).await;
+    Ok(warp::reply::json(&leaderboard))
+}
@@ -0,0 +1,107 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use tracing::error;
+
+// Everything exposed on /metrics. Gauges that mirror app state, like the
+// rounds by state, are set right before rendering.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub logins: IntCounterVec,
+    pub actions: IntCounterVec,
+    pub queue_depth: IntGauge,
+    pub tick_duration: Histogram,
+    pub sse_connections: IntGauge,
+    pub rounds: IntGaugeVec,
+    pub db_query_duration: HistogramVec,
+    pub task_crashes: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let logins = IntCounterVec::new(
+            Opts::new("rocketjam_logins_total", "Login attempts by result"),
+            &["result"],
+        )
+        .unwrap();
+        let actions = IntCounterVec::new(
+            Opts::new("rocketjam_actions_total", "Actions processed by type"),
+            &["action"],
+        )
+        .unwrap();
+        let queue_depth = IntGauge::new(
+            "rocketjam_processor_queue_depth",
+            "Actions waiting for the processor",
+        )
+        .unwrap();
+        let tick_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "rocketjam_tick_duration_seconds",
+                "Time spent in a game tick",
+            )
+            .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]),
+        )
+        .unwrap();
+        let sse_connections =
+            IntGauge::new("rocketjam_sse_connections", "Open event streams").unwrap();
+        let rounds = IntGaugeVec::new(
+            Opts::new("rocketjam_rounds", "Rounds in memory by state"),
+            &["state"],
+        )
+        .unwrap();
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rocketjam_db_query_duration_seconds",
+                "Database query latency",
+            ),
+            &["query"],
+        )
+        .unwrap();
+
+        let task_crashes = IntCounterVec::new(
+            Opts::new(
+                "rocketjam_task_crashes_total",
+                "Panics of supervised background tasks",
+            ),
+            &["task"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry.register(Box::new(actions.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(tick_duration.clone())).unwrap();
+        registry
+            .register(Box::new(sse_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(rounds.clone())).unwrap();
+        registry
+            .register(Box::new(db_query_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(task_crashes.clone())).unwrap();
+
+        Metrics {
+            registry,
+            logins,
+            actions,
+            queue_depth,
+            tick_duration,
+            sse_connections,
+            rounds,
+            db_query_duration,
+            task_crashes,
+        }
+    }
+
+    // The Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Can't encode metrics: {:?}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -0,0 +1,139 @@
+use crate::app::ToClient;
+
+// Bumped whenever ToBackend, ToClient or ToClientEnvelope change in a way
+// clients already loaded in a browser can't decode.
+//  1: clients from before versioning, they don't send one. Not served,
+//     their login fails with a message to reload the page.
+//  2: UpgradeRequired, ServerNotice
+//  3: GameSnapshot, GameDelta, Resync
+//  4: JoinFailed
+pub const PROTOCOL_VERSION: u32 = 4;
+// what clients from before versioning speak
+pub const UNVERSIONED: u32 = 1;
+// the oldest version still served during rollouts, see `downgrade`
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+// the version each message came with, clients below it get it downgraded
+pub const DELTAS_SINCE: u32 = 3;
+pub const JOIN_FAILED_SINCE: u32 = 4;
+
+pub fn is_supported(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+// older clients get every state in full, see delta.rs
+pub fn supports_deltas(version: u32) -> bool {
+    version >= DELTAS_SINCE
+}
+
+// `to_client` in a shape a client speaking `version` can decode, None if
+// there is none and it has to do without
+pub fn downgrade(to_client: &ToClient, version: u32) -> Option<ToClient> {
+    match to_client {
+        ToClient::JoinFailed { message } if version < JOIN_FAILED_SINCE => {
+            Some(ToClient::ServerNotice {
+                message: message.clone(),
+            })
+        }
+        ToClient::GameSnapshot { client_state, .. } if version < DELTAS_SINCE => {
+            Some(ToClient::UpdateGameState {
+                client_state: client_state.clone(),
+            })
+        }
+        // without the snapshot it builds on it's of no use
+        ToClient::GameDelta { .. } if version < DELTAS_SINCE => None,
+        _ => Some(to_client.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::{ClientState, RoundSummary},
+        delta::InGameDelta,
+    };
+
+    fn finished() -> ClientState {
+        ClientState::Finished {
+            levels_completed: 2,
+            instructions_executed: 10,
+            instructions_missed: 1,
+        }
+    }
+
+    fn current_messages() -> Vec<ToClient> {
+        vec![
+            ToClient::HelloClient,
+            ToClient::UpdateGameState {
+                client_state: finished(),
+            },
+            ToClient::AvailableRounds {
+                rounds: Vec::<RoundSummary>::new(),
+                running_rounds: Vec::new(),
+                decks: vec!["default".to_string()],
+            },
+            ToClient::ServerNotice {
+                message: "Restarting soon".to_string(),
+            },
+            ToClient::GameSnapshot {
+                seq: 1,
+                client_state: finished(),
+            },
+            ToClient::GameDelta {
+                seq: 2,
+                delta: InGameDelta::default(),
+            },
+            ToClient::JoinFailed {
+                message: "Wrong password".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn unversioned_clients_are_refused() {
+        assert!(!is_supported(UNVERSIONED));
+        assert!(is_supported(MIN_PROTOCOL_VERSION));
+        assert!(is_supported(PROTOCOL_VERSION));
+        assert!(!is_supported(PROTOCOL_VERSION + 1));
+    }
+
+    #[test]
+    fn join_failures_become_notices_before_their_version() {
+        let join_failed = ToClient::JoinFailed {
+            message: "Wrong password".to_string(),
+        };
+        for version in MIN_PROTOCOL_VERSION..JOIN_FAILED_SINCE {
+            assert_eq!(
+                downgrade(&join_failed, version),
+                Some(ToClient::ServerNotice {
+                    message: "Wrong password".to_string(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn states_come_in_full_before_deltas() {
+        for version in MIN_PROTOCOL_VERSION..DELTAS_SINCE {
+            let downgraded: Vec<ToClient> = current_messages()
+                .iter()
+                .filter_map(|to_client| downgrade(to_client, version))
+                .collect();
+            assert!(downgraded.iter().all(|to_client| !matches!(
+                to_client,
+                ToClient::GameSnapshot { .. } | ToClient::GameDelta { .. }
+            )));
+        }
+    }
+
+    #[test]
+    fn current_version_gets_everything_as_is() {
+        for to_client in current_messages() {
+            assert_eq!(
+                downgrade(&to_client, PROTOCOL_VERSION),
+                Some(to_client.clone())
+            );
+        }
+    }
+}
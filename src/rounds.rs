@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgPool};
+use tracing::error;
+
+use crate::{
+    app::{RoundEvent, RoundId, RoundSnapshot},
+    user::UserId,
+};
+
+// How a round came to an end
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "round_outcome", rename_all = "lowercase")]
+pub enum RoundOutcome {
+    // the last level was played through
+    Completed,
+    // by an operator, or from the lobby
+    Ended,
+    // everybody left before it was over
+    Abandoned,
+}
+
+#[derive(Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct GameHistoryEntry {
+    pub round_id: RoundId,
+    pub deck: String,
+    pub level: i32,
+    pub instructions_executed: i32,
+    pub instructions_missed: i32,
+    pub finished: bool,
+    pub outcome: Option<RoundOutcome>,
+    // milliseconds since the epoch
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+// What the persister does with a round, in the order the game loop sent them
+pub enum RoundWrite {
+    Save(RoundSnapshot),
+    Delete(RoundId),
+}
+
+#[derive(Clone)]
+pub struct RoundServiceImpl {
+    pool: PgPool,
+}
+
+impl RoundServiceImpl {
+    pub fn new(pool: &PgPool) -> RoundServiceImpl {
+        RoundServiceImpl { pool: pool.clone() }
+    }
+
+    pub async fn save_round(&self, round: &RoundSnapshot) {
+        let result = sqlx::query(
+            "INSERT INTO rounds
+                (id, deck, seed, level, instructions_executed, instructions_missed,
+                 started_at, finished_at, outcome)
+             VALUES ($1, $2, $3, $4, $5, $6,
+                 CASE WHEN $7 THEN now() END, CASE WHEN $8 IS NOT NULL THEN now() END, $8)
+             ON CONFLICT (id) DO UPDATE SET
+                level = EXCLUDED.level,
+                instructions_executed = EXCLUDED.instructions_executed,
+                instructions_missed = EXCLUDED.instructions_missed,
+                updated_at = now(),
+                started_at = COALESCE(rounds.started_at, EXCLUDED.started_at),
+                finished_at = COALESCE(rounds.finished_at, EXCLUDED.finished_at),
+                outcome = COALESCE(rounds.outcome, EXCLUDED.outcome)",
+        )
+        .bind(&round.round_id)
+        .bind(&round.deck)
+        // stored bit for bit, BIGINT has no unsigned variant
+        .bind(round.seed as i64)
+        .bind(round.level as i32)
+        .bind(round.instructions_executed as i32)
+        .bind(round.instructions_missed as i32)
+        .bind(round.started)
+        .bind(round.outcome)
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = result {
+            error!("Couldn't save round {:?}: {:?}", round.round_id, e);
+            return;
+        }
+        for (i, event) in round.new_events.iter().enumerate() {
+            let result = sqlx::query(
+                "INSERT INTO round_events (round_id, seq, event) VALUES ($1, $2, $3)
+                 ON CONFLICT (round_id, seq) DO NOTHING",
+            )
+            .bind(&round.round_id)
+            .bind((round.first_seq + i) as i32)
+            .bind(Json(event))
+            .execute(&self.pool)
+            .await;
+            if let Err(e) = result {
+                error!(
+                    "Couldn't save event {:?} of round {:?}: {:?}",
+                    round.first_seq + i,
+                    round.round_id,
+                    e
+                );
+                return;
+            }
+        }
+        // who left the lobby before the start never played it
+        if !round.started {
+            return;
+        }
+        for user_id in &round.players {
+            let stats = round.player_stats.get(user_id).copied().unwrap_or_default();
+            let result = sqlx::query(
+                "INSERT INTO round_participants
+                    (round_id, user_id, changes, hits, reaction_ms_total, reactions)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (round_id, user_id) DO UPDATE SET
+                    changes = EXCLUDED.changes,
+                    hits = EXCLUDED.hits,
+                    reaction_ms_total = EXCLUDED.reaction_ms_total,
+                    reactions = EXCLUDED.reactions",
+            )
+            .bind(&round.round_id)
+            .bind(user_id)
+            .bind(stats.changes as i32)
+            .bind(stats.hits as i32)
+            .bind(stats.reaction_ms_total as i64)
+            .bind(stats.reactions as i32)
+            .execute(&self.pool)
+            .await;
+            if let Err(e) = result {
+                error!(
+                    "Couldn't save participant {:?} of round {:?}: {:?}",
+                    user_id, round.round_id, e
+                );
+            }
+        }
+    }
+
+    pub async fn delete_round(&self, round_id: &RoundId) {
+        let result = sqlx::query("DELETE FROM rounds WHERE id = $1")
+            .bind(round_id)
+            .execute(&self.pool)
+            .await;
+        if let Err(e) = result {
+            error!("Couldn't delete round {:?}: {:?}", round_id, e);
+        }
+    }
+
+    pub async fn find_unfinished_round_logs(&self) -> Vec<Vec<RoundEvent>> {
+        let query_result = sqlx::query_as::<_, (RoundId, Json<RoundEvent>)>(
+            "SELECT e.round_id, e.event
+             FROM round_events e
+             JOIN rounds r ON r.id = e.round_id
+             WHERE r.outcome IS NULL
+             ORDER BY r.created_at, e.round_id, e.seq",
+        )
+        .fetch_all(&self.pool)
+        .await;
+        match query_result {
+            Ok(rows) => {
+                let mut round_logs: Vec<(RoundId, Vec<RoundEvent>)> = Vec::new();
+                for (round_id, Json(event)) in rows {
+                    match round_logs.last_mut() {
+                        Some((last_id, events)) if *last_id == round_id => events.push(event),
+                        _ => round_logs.push((round_id, vec![event])),
+                    }
+                }
+                round_logs.into_iter().map(|(_, events)| events).collect()
+            }
+            Err(e) => {
+                error!("Couldn't load unfinished rounds: {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    pub async fn find_round_log(&self, round_id: &RoundId) -> Option<Vec<RoundEvent>> {
+        let query_result = sqlx::query_as::<_, (Json<RoundEvent>,)>(
+            "SELECT event FROM round_events WHERE round_id = $1 ORDER BY seq",
+        )
+        .bind(round_id)
+        .fetch_all(&self.pool)
+        .await;
+        match query_result {
+            Ok(rows) if rows.is_empty() => None,
+            Ok(rows) => Some(rows.into_iter().map(|(Json(event),)| event).collect()),
+            Err(e) => {
+                error!("Couldn't load the log of round {:?}: {:?}", round_id, e);
+                None
+            }
+        }
+    }
+
+    pub async fn find_history_for_user(&self, user_id: UserId) -> Vec<GameHistoryEntry> {
+        let query_result = sqlx::query_as::<_, GameHistoryEntry>(
+            "SELECT
+                r.id AS round_id,
+                r.deck,
+                r.level,
+                r.instructions_executed,
+                r.instructions_missed,
+                r.outcome IS NOT NULL AS finished,
+                r.outcome,
+                (EXTRACT(EPOCH FROM r.created_at) * 1000)::BIGINT AS created_at,
+                (EXTRACT(EPOCH FROM r.finished_at) * 1000)::BIGINT AS finished_at
+             FROM rounds r
+             JOIN round_participants p ON p.round_id = r.id
+             WHERE p.user_id = $1
+             ORDER BY r.created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await;
+        match query_result {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Couldn't load history for user {:?}: {:?}", user_id, e);
+                vec![]
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use warp::{
+    ws::{Message, WebSocket, Ws},
+    Filter, Rejection, Reply,
+};
+
+use crate::{
+    backend_messages::ToBackendEnvelope,
+    env::{Client, Env},
+    with_env,
+};
+
+// How the envelopes on a socket are framed, picked per connection with
+// `?encoding=`. SSE is text only, its events stay JSON.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    // JSON goes into text frames, MessagePack into binary ones
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Message, String> {
+        match self {
+            Encoding::Json => serde_json::to_string(value)
+                .map(Message::text)
+                .map_err(|e| e.to_string()),
+            // named, so structs keep their field names like they do in JSON
+            Encoding::MessagePack => rmp_serde::to_vec_named(value)
+                .map(Message::binary)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, message: &Message) -> Result<T, String> {
+        match self {
+            Encoding::Json => {
+                let text = message
+                    .to_str()
+                    .map_err(|_| "expected a text frame".to_string())?;
+                serde_json::from_str(text).map_err(|e| e.to_string())
+            }
+            Encoding::MessagePack => {
+                rmp_serde::from_slice(message.as_bytes()).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SocketQuery {
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+// Events and actions over one WebSocket, an alternative to /events and /action
+pub fn route(
+    env: Env,
+    sender: Sender<ToBackendEnvelope>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("socket" / String)
+        .and(warp::ws())
+        .and(warp::query::<SocketQuery>())
+        .and(warp::any().map(move || sender.clone()))
+        .and(with_env(env))
+        .and_then(socket_handler)
+}
+
+async fn socket_handler(
+    token: String,
+    ws: Ws,
+    query: SocketQuery,
+    sender: Sender<ToBackendEnvelope>,
+    env: Env,
+) -> Result<impl Reply, Rejection> {
+    match env.client_broadcaster.get(&token).await {
+        Some(client) => Ok(ws
+            // same limit as the body of /action
+            .max_message_size(1024 * 16)
+            .on_upgrade(move |socket| serve(socket, client, query.encoding, sender, env))),
+        None => Err(warp::reject::not_found()),
+    }
+}
+
+async fn serve(
+    socket: WebSocket,
+    client: Client,
+    encoding: Encoding,
+    sender: Sender<ToBackendEnvelope>,
+    env: Env,
+) {
+    info!(
+        "User {:?} connected a socket speaking {:?}",
+        client.user_id, encoding
+    );
+    let (mut socket_tx, mut socket_rx) = socket.split();
+    let token = client.token.clone();
+    let rx = env.client_broadcaster.connect(client).await;
+    let mut rx = UnboundedReceiverStream::new(rx);
+
+    let outgoing = tokio::spawn(async move {
+        while let Some(outgoing) = rx.next().await {
+            info!(request_id = ?outgoing.request_id, "Sending event to client {:?}", outgoing.envelope);
+            let message = match encoding.encode(&outgoing.envelope) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Can't encode {:?}: {}", outgoing.envelope, e);
+                    continue;
+                }
+            };
+            if let Err(e) = socket_tx.send(message).await {
+                warn!("Cannot send {:?}", e);
+                break;
+            }
+        }
+        // superseded by another connection, or gone
+        let _ = socket_tx.close().await;
+    });
+
+    while let Some(result) = socket_rx.next().await {
+        let message = match result {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Socket failed {:?}", e);
+                break;
+            }
+        };
+        if message.is_close() {
+            break;
+        }
+        if !message.is_text() && !message.is_binary() {
+            continue;
+        }
+        let mut action: ToBackendEnvelope = match encoding.decode(&message) {
+            Ok(action) => action,
+            Err(e) => {
+                warn!("Can't decode action: {}", e);
+                continue;
+            }
+        };
+        // the socket speaks for the session it was opened with, and only as
+        // long as that lasts, like /action does per request
+        if action.token() != token {
+            warn!("Dropping action for another session than the socket's");
+            continue;
+        }
+        if env.client_broadcaster.get(&token).await.is_none() {
+            info!("Session ended, closing the socket");
+            break;
+        }
+        // there are no headers per message, every action gets a fresh one
+        action.request_id = Some(Uuid::new_v4().to_string());
+        info!("Received action {}", action.name());
+        // counted before sending, see action_handler
+        env.metrics.queue_depth.inc();
+        if let Err(e) = sender.send(action).await {
+            env.metrics.queue_depth.dec();
+            error!("Can't queue action, the processor is gone: {:?}", e);
+            break;
+        }
+    }
+    // drops the receiver, so the client counts as disconnected
+    outgoing.abort();
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        app::{ClientState, ClientUiItem, ControlType, ToClient},
+        chat::{ChatBody, ChatEntry, Emote},
+        env::ToClientEnvelope,
+    };
+
+    fn round_trip<T>(encoding: Encoding, value: &T) -> T
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let message = encoding.encode(value).unwrap();
+        encoding.decode(&message).unwrap()
+    }
+
+    fn to_client_envelopes() -> Vec<ToClientEnvelope> {
+        vec![
+            ToClientEnvelope::SuperSeeded(),
+            ToClientEnvelope::UpgradeRequired {
+                server_version: 3,
+                min_version: 1,
+            },
+            ToClientEnvelope::AppMsg(ToClient::HelloClient),
+            ToClientEnvelope::AppMsg(ToClient::QueuePosition {
+                position: None,
+                queue_length: 2,
+            }),
+            ToClientEnvelope::AppMsg(ToClient::ChatMessage {
+                message: ChatEntry {
+                    user_id: 3,
+                    username: "cy".to_string(),
+                    body: ChatBody::Emote(Emote::NiceOne),
+                    sent_at_ms: 1_700_000_000_000,
+                },
+            }),
+            ToClientEnvelope::AppMsg(ToClient::GameSnapshot {
+                seq: 7,
+                client_state: ClientState::InGame {
+                    current_instruction: "Set Flux to 2".to_string(),
+                    ui_items: vec![ClientUiItem {
+                        id: 0,
+                        label: "Flux".to_string(),
+                        state: 1,
+                        control_type: ControlType::Slider,
+                        max_value: 3,
+                    }],
+                    instructions_executed: 4,
+                    instructions_missed: 1,
+                    level: 2,
+                    level_count: 5,
+                    level_progress: 3,
+                    level_target: 12,
+                },
+            }),
+        ]
+    }
+
+    fn to_backend_envelopes() -> Vec<ToBackendEnvelope> {
+        [
+            json!({"token": "abc", "to_backend": "Init"}),
+            json!({"token": "abc", "to_backend": {"ChangeSetting": {"item_id": 2, "value": 1}}}),
+            json!({"token": "abc", "to_backend": {"JoinGame": {"round_id": "r1", "password": null}}}),
+            json!({
+                "token": "abc",
+                "to_backend": {"StartGame": {"deck": "default", "seed": null, "name": "Fun", "password": "pw", "private": true}},
+                "request_id": "req-1"
+            }),
+        ]
+        .into_iter()
+        .map(|json| serde_json::from_value(json).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn to_client_envelopes_round_trip() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            for envelope in to_client_envelopes() {
+                assert_eq!(round_trip(encoding, &envelope), envelope);
+            }
+        }
+    }
+
+    #[test]
+    fn to_backend_envelopes_round_trip() {
+        for encoding in [Encoding::Json, Encoding::MessagePack] {
+            for envelope in to_backend_envelopes() {
+                assert_eq!(round_trip(encoding, &envelope), envelope);
+            }
+        }
+    }
+
+    #[test]
+    fn message_pack_goes_into_binary_frames() {
+        let message = Encoding::MessagePack
+            .encode(&ToClientEnvelope::SuperSeeded())
+            .unwrap();
+        assert!(message.is_binary());
+        assert!(Encoding::Json.decode::<ToClientEnvelope>(&message).is_err());
+    }
+}
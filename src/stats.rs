@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::user::UserId;
+
+const LEADERBOARD_SIZE: i64 = 20;
+
+#[derive(Serialize, Clone, Debug, sqlx::FromRow)]
+pub struct UserStats {
+    pub user_id: UserId,
+    pub username: String,
+    // rounds that got past the lobby
+    pub games_played: i64,
+    // rounds that completed every level
+    pub wins: i64,
+    pub changes: i64,
+    pub hits: i64,
+    // share of setting changes that completed an instruction
+    pub accuracy: f64,
+    pub average_reaction_ms: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaderboardPeriod {
+    #[default]
+    All,
+    Week,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LeaderboardQuery {
+    #[serde(default)]
+    pub period: LeaderboardPeriod,
+}
+
+// Aggregates round_participants per user over the rounds started since $1
+const USER_STATS_SELECT: &str = "
+    SELECT
+        u.id AS user_id,
+        u.username,
+        COUNT(r.id) AS games_played,
+        COUNT(r.id) FILTER (WHERE r.outcome = 'completed') AS wins,
+        COALESCE(SUM(p.changes), 0)::BIGINT AS changes,
+        COALESCE(SUM(p.hits), 0)::BIGINT AS hits,
+        COALESCE(SUM(p.hits)::FLOAT8 / NULLIF(SUM(p.changes), 0), 0) AS accuracy,
+        SUM(p.reaction_ms_total)::FLOAT8 / NULLIF(SUM(p.reactions), 0) AS average_reaction_ms
+    FROM users u
+    LEFT JOIN (
+        round_participants p
+        JOIN rounds r ON r.id = p.round_id AND r.started_at >= to_timestamp($1)
+    ) ON p.user_id = u.id";
+
+#[derive(Clone)]
+pub struct StatsServiceImpl {
+    pool: PgPool,
+}
+
+impl StatsServiceImpl {
+    pub fn new(pool: &PgPool) -> StatsServiceImpl {
+        StatsServiceImpl { pool: pool.clone() }
+    }
+
+    pub async fn find_user_stats(&self, user_id: UserId) -> Option<UserStats> {
+        let query = format!(
+            "{} WHERE u.id = $2 GROUP BY u.id, u.username",
+            USER_STATS_SELECT
+        );
+        let query_result = sqlx::query_as::<_, UserStats>(&query)
+            .bind(since(LeaderboardPeriod::All))
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await;
+        match query_result {
+            Ok(stats) => stats,
+            Err(e) => {
+                error!("Couldn't load stats for user {:?}: {:?}", user_id, e);
+                None
+            }
+        }
+    }
+
+    pub async fn find_leaderboard(&self, period: LeaderboardPeriod) -> Vec<UserStats> {
+        let query = format!(
+            "{} GROUP BY u.id, u.username
+             HAVING COUNT(r.id) > 0
+             ORDER BY wins DESC, hits DESC, average_reaction_ms ASC NULLS LAST
+             LIMIT $2",
+            USER_STATS_SELECT
+        );
+        let query_result = sqlx::query_as::<_, UserStats>(&query)
+            .bind(since(period))
+            .bind(LEADERBOARD_SIZE)
+            .fetch_all(&self.pool)
+            .await;
+        match query_result {
+            Ok(leaderboard) => leaderboard,
+            Err(e) => {
+                error!("Couldn't load {:?} leaderboard: {:?}", period, e);
+                vec![]
+            }
+        }
+    }
+}
+
+// Start of the period as epoch seconds
+fn since(period: LeaderboardPeriod) -> f64 {
+    match period {
+        LeaderboardPeriod::All => 0.0,
+        LeaderboardPeriod::Week => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs_f64())
+                .unwrap_or(0.0);
+            now - 7.0 * 24.0 * 60.0 * 60.0
+        }
+    }
+}
+
+// These run against the database the server uses, with a user of their own
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use sqlx::postgres::PgPoolOptions;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        app::{PlayerStats, RoundSnapshot},
+        rounds::{RoundOutcome, RoundServiceImpl},
+    };
+
+    fn round(
+        user_id: UserId,
+        started: bool,
+        outcome: RoundOutcome,
+        stats: PlayerStats,
+    ) -> RoundSnapshot {
+        RoundSnapshot {
+            round_id: Uuid::new_v4().to_string(),
+            deck: "classic".to_string(),
+            seed: 1,
+            players: vec![user_id],
+            level: 0,
+            instructions_executed: stats.hits,
+            instructions_missed: 0,
+            started,
+            outcome: Some(outcome),
+            player_stats: HashMap::from([(user_id, stats)]),
+            first_seq: 0,
+            new_events: vec![],
+        }
+    }
+
+    // a database of its own, e.g. postgres://rust@localhost/rust_server_test,
+    // it gets migrated and written to
+    fn test_database_url() -> String {
+        std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL isn't set")
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database in TEST_DATABASE_URL"]
+    async fn only_completed_rounds_are_wins() {
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&test_database_url())
+            .await
+            .expect("the stats tests need the database");
+        sqlx::migrate!().run(&pool).await.unwrap();
+        let (user_id,): (UserId,) = sqlx::query_as(
+            "INSERT INTO users (username, hashed_password) VALUES ($1, '') RETURNING id",
+        )
+        .bind(format!("stats-test-{}", Uuid::new_v4()))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        let rounds = vec![
+            round(
+                user_id,
+                true,
+                RoundOutcome::Completed,
+                PlayerStats {
+                    changes: 5,
+                    hits: 4,
+                    reaction_ms_total: 4000,
+                    reactions: 4,
+                },
+            ),
+            round(
+                user_id,
+                true,
+                RoundOutcome::Abandoned,
+                PlayerStats {
+                    changes: 6,
+                    hits: 2,
+                    reaction_ms_total: 3000,
+                    reactions: 2,
+                },
+            ),
+            // ended from the lobby, it never got played
+            round(user_id, false, RoundOutcome::Ended, PlayerStats::default()),
+        ];
+        let round_service = RoundServiceImpl::new(&pool);
+        for round in &rounds {
+            round_service.save_round(round).await;
+        }
+        let stats = StatsServiceImpl::new(&pool).find_user_stats(user_id).await;
+
+        for round in &rounds {
+            round_service.delete_round(&round.round_id).await;
+        }
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let stats = stats.unwrap();
+        assert_eq!(stats.games_played, 2);
+        assert_eq!(stats.wins, 1);
+        assert_eq!(stats.changes, 11);
+        assert_eq!(stats.hits, 6);
+        assert_eq!(stats.accuracy, 6.0 / 11.0);
+        assert_eq!(stats.average_reaction_ms, Some(7000.0 / 6.0));
+    }
+}
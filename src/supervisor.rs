@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use tokio::{task::JoinHandle, time::sleep};
+use tracing::{error, info, warn};
+
+use crate::{
+    health::{HealthServiceImpl, TaskStatus},
+    metrics::Metrics,
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// a task that ran this long before crashing starts over with the initial backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+// Owns the background loops, restarting them with backoff when they panic
+pub struct Supervisor {
+    health_service: HealthServiceImpl,
+    metrics: Metrics,
+}
+
+impl Supervisor {
+    pub fn new(health_service: &HealthServiceImpl, metrics: &Metrics) -> Self {
+        Supervisor {
+            health_service: health_service.clone(),
+            metrics: metrics.clone(),
+        }
+    }
+
+    // `start` spawns a fresh instance of the loop, it's called again after every crash
+    pub fn supervise<F>(&self, name: &'static str, start: F)
+    where
+        F: Fn() -> JoinHandle<()> + Send + 'static,
+    {
+        let health_service = self.health_service.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                health_service.set_status(name, TaskStatus::Running).await;
+                let started_at = Instant::now();
+                let panic = match start().await {
+                    Ok(()) => {
+                        warn!("Background task {:?} stopped", name);
+                        health_service.set_status(name, TaskStatus::Stopped).await;
+                        return;
+                    }
+                    Err(e) if e.is_panic() => panic_message(e.into_panic()),
+                    Err(e) => {
+                        warn!("Background task {:?} was cancelled: {:?}", name, e);
+                        health_service.set_status(name, TaskStatus::Stopped).await;
+                        return;
+                    }
+                };
+                metrics.task_crashes.with_label_values(&[name]).inc();
+                if started_at.elapsed() >= STABLE_AFTER {
+                    backoff = INITIAL_BACKOFF;
+                }
+                error!(
+                    "Background task {:?} panicked: {}, restarting in {:?}",
+                    name, panic, backoff
+                );
+                health_service
+                    .set_status(name, TaskStatus::Panicked(panic))
+                    .await;
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                info!("Restarting background task {:?}", name);
+            }
+        });
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(msg), _) => msg.to_string(),
+        (_, Some(msg)) => msg.clone(),
+        _ => "unknown panic".to_string(),
+    }
+}
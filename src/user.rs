@@ -1,29 +1,47 @@
 use std::{collections::HashMap, sync::Arc};
 
-use log::error;
+use prometheus::HistogramVec;
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use tokio::sync::RwLock;
+use tracing::error;
+
+use crate::metrics::Metrics;
 
 pub type UserId = i32;
 
+// Ordered by privilege, every role may do what the ones before it may
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, sqlx::Type,
+)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+pub enum Role {
+    Player,
+    Moderator,
+    Admin,
+}
+
 #[derive(Clone, sqlx::FromRow)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub hashed_password: String,
+    pub role: Role,
 }
 
 #[derive(Clone)]
 pub struct UserServiceImpl {
     pool: PgPool,
     user_cache: Arc<RwLock<HashMap<UserId, User>>>,
+    query_duration: HistogramVec,
 }
 
 impl UserServiceImpl {
-    pub fn new(pool: &PgPool) -> UserServiceImpl {
+    pub fn new(pool: &PgPool, metrics: &Metrics) -> UserServiceImpl {
         UserServiceImpl {
             pool: pool.clone(),
             user_cache: Arc::new(RwLock::new(HashMap::new())),
+            query_duration: metrics.db_query_duration.clone(),
         }
     }
 
@@ -34,12 +52,17 @@ impl UserServiceImpl {
         match maybe_user {
             Some(user) => Some(user.clone()),
             None => {
+                let timer = self
+                    .query_duration
+                    .with_label_values(&["find_user"])
+                    .start_timer();
                 let user_query_result = sqlx::query_as::<_, User>(
-                    "SELECT id, username, hashed_password FROM users WHERE id = $1",
+                    "SELECT id, username, hashed_password, role FROM users WHERE id = $1",
                 )
                 .bind(user_id)
                 .fetch_one(&self.pool)
                 .await;
+                timer.observe_duration();
                 match user_query_result {
                     Ok(user) => {
                         // THIS PRODUCES A DEADLOCK
@@ -60,12 +83,17 @@ impl UserServiceImpl {
         username: &String,
         password: &String,
     ) -> Option<User> {
+        let timer = self
+            .query_duration
+            .with_label_values(&["find_user_by_name_and_password"])
+            .start_timer();
         let user_query_result = sqlx::query_as::<_, User>(
-            "SELECT id, username, hashed_password FROM users WHERE username = $1",
+            "SELECT id, username, hashed_password, role FROM users WHERE username = $1",
         )
         .bind(username)
         .fetch_one(&self.pool)
         .await;
+        timer.observe_duration();
         match user_query_result {
             Ok(user) => {
                 if user.hashed_password.eq(password) {